@@ -1,20 +1,348 @@
-#[derive(Clone)]
-pub enum BuilderState {
-    Idle,
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager};
+
+// Number of finished jobs kept around so their result can still be queried.
+const FINISHED_JOBS_LIMIT: usize = 50;
+
+pub type JobId = u64;
+
+#[derive(Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Download,
+    Compress,
+    Unzip,
+    Install,
+    // Checking an offline bundle
+    Validate,
+    Flash,
+    Monitor,
+}
+
+#[derive(Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
     Running,
-    Abort,
-    Done,
+    Cancelling,
+    Finished,
+    Failed,
+    Cancelled,
 }
 
-#[derive(Clone)]
+// Cancellation flag shared between the registry and the task running the job.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+struct Job {
+    kind: JobKind,
+    // What the job works on, e.g. the serial port or the ESP-IDF version
+    target: String,
+    status: JobStatus,
+    token: CancellationToken,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct JobInfo {
+    pub id: JobId,
+    pub kind: JobKind,
+    pub target: String,
+    pub status: JobStatus,
+}
+
+#[derive(Default)]
 pub struct AppState {
-    pub builder: BuilderState,
+    jobs: BTreeMap<JobId, Job>,
+    next_job_id: JobId,
+}
+
+impl AppState {
+    fn job_info(&self, id: JobId) -> Option<JobInfo> {
+        self.jobs.get(&id).map(|job| JobInfo {
+            id,
+            kind: job.kind,
+            target: job.target.clone(),
+            status: job.status,
+        })
+    }
+
+    pub fn start_job(&mut self, kind: JobKind, target: &str) -> (JobId, CancellationToken) {
+        self.prune_finished_jobs();
+
+        let id = self.next_job_id;
+        self.next_job_id += 1;
+        let token = CancellationToken::default();
+        self.jobs.insert(
+            id,
+            Job {
+                kind,
+                target: target.to_string(),
+                status: JobStatus::Running,
+                token: token.clone(),
+            },
+        );
+        (id, token)
+    }
+
+    pub fn finish_job(&mut self, id: JobId, succeeded: bool) {
+        if let Some(job) = self.jobs.get_mut(&id) {
+            job.status = if job.token.is_cancelled() {
+                JobStatus::Cancelled
+            } else if succeeded {
+                JobStatus::Finished
+            } else {
+                JobStatus::Failed
+            };
+        }
+    }
+
+    // Returns false when the job does not exist or is not running anymore.
+    pub fn cancel_job(&mut self, id: JobId) -> bool {
+        match self.jobs.get_mut(&id) {
+            Some(job) if job.status == JobStatus::Running => {
+                job.token.cancel();
+                job.status = JobStatus::Cancelling;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Cancel all running jobs, or only those of the given kind and target.
+    pub fn cancel_jobs(&mut self, kind: Option<JobKind>, target: Option<&str>) -> Vec<JobId> {
+        let ids: Vec<JobId> = self
+            .jobs
+            .iter()
+            .filter(|(_, job)| kind.map_or(true, |kind| job.kind == kind))
+            .filter(|(_, job)| target.map_or(true, |target| job.target == target))
+            .map(|(id, _)| *id)
+            .collect();
+        ids.into_iter().filter(|id| self.cancel_job(*id)).collect()
+    }
+
+    pub fn jobs(&self) -> Vec<JobInfo> {
+        self.jobs
+            .keys()
+            .filter_map(|id| self.job_info(*id))
+            .collect()
+    }
+
+    pub fn job(&self, id: JobId) -> Option<JobInfo> {
+        self.job_info(id)
+    }
+
+    fn prune_finished_jobs(&mut self) {
+        let finished: Vec<JobId> = self
+            .jobs
+            .iter()
+            .filter(|(_, job)| !matches!(job.status, JobStatus::Running | JobStatus::Cancelling))
+            .map(|(id, _)| *id)
+            .collect();
+        if finished.len() > FINISHED_JOBS_LIMIT {
+            for id in &finished[..finished.len() - FINISHED_JOBS_LIMIT] {
+                self.jobs.remove(id);
+            }
+        }
+    }
 }
 
-impl Default for AppState {
-    fn default() -> Self {
-        Self {
-            builder: BuilderState::Idle,
+fn emit_job_update(app: &AppHandle, info: Option<JobInfo>) {
+    if let Some(info) = info {
+        let _ = app.emit_all("job-update", info);
+    }
+}
+
+// Register a job and notify the frontend, the token has to be checked by the job.
+pub fn start_job(app: &AppHandle, kind: JobKind, target: &str) -> (JobId, CancellationToken) {
+    let state_mutex = app.state::<Mutex<AppState>>();
+    let mut state = state_mutex.lock().unwrap();
+    let (id, token) = state.start_job(kind, target);
+    emit_job_update(app, state.job(id));
+    (id, token)
+}
+
+pub fn finish_job(app: &AppHandle, id: JobId, succeeded: bool) {
+    let state_mutex = app.state::<Mutex<AppState>>();
+    let mut state = state_mutex.lock().unwrap();
+    state.finish_job(id, succeeded);
+    emit_job_update(app, state.job(id));
+}
+
+pub fn cancel_jobs(app: &AppHandle, kind: Option<JobKind>, target: Option<&str>) {
+    let state_mutex = app.state::<Mutex<AppState>>();
+    let mut state = state_mutex.lock().unwrap();
+    for id in state.cancel_jobs(kind, target) {
+        emit_job_update(app, state.job(id));
+    }
+}
+
+pub fn cancel_job(app: &AppHandle, id: JobId) -> bool {
+    let state_mutex = app.state::<Mutex<AppState>>();
+    let mut state = state_mutex.lock().unwrap();
+    let cancelled = state.cancel_job(id);
+    if cancelled {
+        emit_job_update(app, state.job(id));
+    }
+    cancelled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(state: &AppState, id: JobId) -> Option<JobStatus> {
+        state.job(id).map(|job| job.status)
+    }
+
+    #[test]
+    fn start_jobs() {
+        let mut state = AppState::default();
+        let (first, token) = state.start_job(JobKind::Flash, "/dev/ttyUSB0");
+        let (second, _) = state.start_job(JobKind::Monitor, "/dev/ttyUSB0");
+
+        assert_ne!(first, second);
+        assert!(!token.is_cancelled());
+        let job = state.job(first).unwrap();
+        assert!(job.kind == JobKind::Flash);
+        assert_eq!(job.target, "/dev/ttyUSB0");
+        assert!(job.status == JobStatus::Running);
+        assert_eq!(state.jobs().len(), 2);
+        assert!(state.job(second + 1).is_none());
+    }
+
+    #[test]
+    fn finish_jobs() {
+        // cancelled before finishing, succeeded, expected status
+        let cases = [
+            (false, true, JobStatus::Finished),
+            (false, false, JobStatus::Failed),
+            // A cancelled job is cancelled however it ends
+            (true, true, JobStatus::Cancelled),
+            (true, false, JobStatus::Cancelled),
+        ];
+        for (cancelled, succeeded, expected) in cases {
+            let mut state = AppState::default();
+            let (id, _) = state.start_job(JobKind::Install, "v5.1.2");
+            if cancelled {
+                assert!(state.cancel_job(id));
+            }
+            state.finish_job(id, succeeded);
+            assert!(status(&state, id) == Some(expected));
+        }
+    }
+
+    #[test]
+    fn finish_unknown_job() {
+        let mut state = AppState::default();
+        state.finish_job(42, true);
+        assert!(state.jobs().is_empty());
+    }
+
+    #[test]
+    fn cancel_single_job() {
+        let mut state = AppState::default();
+        let (id, token) = state.start_job(JobKind::Download, "v5.1.2");
+        let (other, other_token) = state.start_job(JobKind::Download, "v5.0.4");
+
+        assert!(state.cancel_job(id));
+        assert!(token.is_cancelled());
+        assert!(status(&state, id) == Some(JobStatus::Cancelling));
+        // Only running jobs can be cancelled
+        assert!(!state.cancel_job(id));
+        assert!(!state.cancel_job(other + 1));
+
+        assert!(!other_token.is_cancelled());
+        state.finish_job(other, true);
+        assert!(!state.cancel_job(other));
+        assert!(!other_token.is_cancelled());
+        assert!(status(&state, other) == Some(JobStatus::Finished));
+    }
+
+    #[test]
+    fn cancel_jobs_by_kind_and_target() {
+        let jobs = [
+            (JobKind::Monitor, "/dev/ttyUSB0"),
+            (JobKind::Monitor, "/dev/ttyUSB1"),
+            (JobKind::Flash, "/dev/ttyUSB0"),
+            (JobKind::Install, "v5.1.2"),
+        ];
+        // kind, target, indices of the cancelled jobs
+        let cases: [(Option<JobKind>, Option<&str>, &[usize]); 6] = [
+            (None, None, &[0, 1, 2, 3]),
+            (Some(JobKind::Monitor), None, &[0, 1]),
+            (Some(JobKind::Monitor), Some("/dev/ttyUSB1"), &[1]),
+            (None, Some("/dev/ttyUSB0"), &[0, 2]),
+            (Some(JobKind::Flash), Some("/dev/ttyUSB1"), &[]),
+            (Some(JobKind::Download), None, &[]),
+        ];
+        for (kind, target, expected) in cases {
+            let mut state = AppState::default();
+            let ids: Vec<JobId> = jobs
+                .iter()
+                .map(|(job_kind, job_target)| state.start_job(*job_kind, job_target).0)
+                .collect();
+            let expected: Vec<JobId> = expected.iter().map(|index| ids[*index]).collect();
+
+            assert_eq!(state.cancel_jobs(kind, target), expected);
+            for id in ids {
+                let cancelling = status(&state, id) == Some(JobStatus::Cancelling);
+                assert_eq!(cancelling, expected.contains(&id));
+            }
+        }
+    }
+
+    #[test]
+    fn cancel_jobs_skips_finished_jobs() {
+        let mut state = AppState::default();
+        let (finished, _) = state.start_job(JobKind::Flash, "/dev/ttyUSB0");
+        state.finish_job(finished, true);
+        let (running, _) = state.start_job(JobKind::Flash, "/dev/ttyUSB0");
+
+        assert_eq!(state.cancel_jobs(Some(JobKind::Flash), None), vec![running]);
+        assert!(status(&state, finished) == Some(JobStatus::Finished));
+    }
+
+    #[test]
+    fn prune_finished_jobs() {
+        let mut state = AppState::default();
+        let (running, _) = state.start_job(JobKind::Monitor, "/dev/ttyUSB0");
+        let finished: Vec<JobId> = (0..FINISHED_JOBS_LIMIT + 5)
+            .map(|_| {
+                let (id, _) = state.start_job(JobKind::Flash, "/dev/ttyUSB0");
+                state.finish_job(id, true);
+                id
+            })
+            .collect();
+
+        // Pruned when the next job starts, the oldest finished jobs go first
+        let (last, _) = state.start_job(JobKind::Flash, "/dev/ttyUSB0");
+        assert_eq!(state.jobs().len(), FINISHED_JOBS_LIMIT + 2);
+        assert!(state.job(running).is_some());
+        assert!(state.job(last).is_some());
+        for (index, id) in finished.iter().enumerate() {
+            assert_eq!(state.job(*id).is_some(), index >= 5, "{}", index);
+        }
+    }
+
+    #[test]
+    fn keep_finished_jobs_up_to_limit() {
+        let mut state = AppState::default();
+        for _ in 0..FINISHED_JOBS_LIMIT {
+            let (id, _) = state.start_job(JobKind::Unzip, "esp-idf.zip");
+            state.finish_job(id, false);
         }
+        state.start_job(JobKind::Unzip, "esp-idf.zip");
+        assert_eq!(state.jobs().len(), FINISHED_JOBS_LIMIT + 1);
     }
 }
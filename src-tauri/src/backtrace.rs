@@ -0,0 +1,149 @@
+use addr2line::gimli::{self, EndianArcSlice, RunTimeEndian};
+use addr2line::object::{self, Object, ObjectSection, ObjectSymbol, SectionKind};
+use addr2line::Context;
+use regex::Regex;
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use crate::device_error::DeviceError;
+use crate::monitor_decoder::{Line, Style};
+
+// Yellow like the decoded lines of idf.py monitor
+const ANNOTATION_STYLE: Style = Style {
+    fg: Some(3),
+    bg: None,
+    bold: false,
+};
+
+type Reader = EndianArcSlice<RunTimeEndian>;
+
+// Debug information of the application running on the chip, used to turn
+// program counters printed on a panic into function names and source locations.
+pub struct Symbols {
+    context: Context<Reader>,
+    // Start address, size and name of the functions in the symbol table,
+    // used when there is no debug information for an address
+    functions: Vec<(u64, u64, String)>,
+    // Address ranges of the sections containing code
+    code: Vec<(u64, u64)>,
+    address_regex: Regex,
+}
+
+impl Symbols {
+    pub fn load(elf_path: &str) -> Result<Self, DeviceError> {
+        let data = std::fs::read(elf_path).map_err(|e| DeviceError::file_read(elf_path, e))?;
+        let invalid_elf = |message: String| DeviceError::FileRead {
+            path: elf_path.to_string(),
+            message,
+        };
+
+        let file = object::File::parse(&*data).map_err(|e| invalid_elf(e.to_string()))?;
+        let endian = if file.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+        let dwarf = gimli::Dwarf::load(|id| -> Result<Reader, gimli::Error> {
+            let section = file
+                .section_by_name(id.name())
+                .and_then(|section| section.uncompressed_data().ok())
+                .unwrap_or(Cow::Borrowed(&[]));
+            Ok(EndianArcSlice::new(Arc::from(&*section), endian))
+        })
+        .map_err(|e| invalid_elf(e.to_string()))?;
+        let context = Context::from_dwarf(dwarf).map_err(|e| invalid_elf(e.to_string()))?;
+
+        let mut functions: Vec<(u64, u64, String)> = file
+            .symbols()
+            .filter(|symbol| symbol.kind() == object::SymbolKind::Text)
+            .filter_map(|symbol| {
+                let name = symbol.name().ok()?;
+                Some((symbol.address(), symbol.size(), name.to_string()))
+            })
+            .collect();
+        functions.sort_by_key(|(address, _, _)| *address);
+
+        let code = file
+            .sections()
+            .filter(|section| section.kind() == SectionKind::Text)
+            .map(|section| (section.address(), section.address() + section.size()))
+            .collect();
+
+        Ok(Symbols {
+            context,
+            functions,
+            code,
+            address_regex: Regex::new(r"0x[0-9a-fA-F]{8}").unwrap(),
+        })
+    }
+
+    fn is_code(&self, address: u64) -> bool {
+        self.code
+            .iter()
+            .any(|(start, end)| (*start..*end).contains(&address))
+    }
+
+    fn symbol_name(&self, address: u64) -> Option<&str> {
+        let index = self
+            .functions
+            .partition_point(|(start, _, _)| *start <= address);
+        let (start, size, name) = self.functions.get(index.checked_sub(1)?)?;
+        (address < start + (*size).max(1)).then_some(name.as_str())
+    }
+
+    // Describe the function at the address, inlined functions get a line each.
+    pub fn lookup(&self, address: u64) -> Vec<String> {
+        let mut descriptions = Vec::new();
+        if let Ok(mut frames) = self.context.find_frames(address).skip_all_loads() {
+            while let Ok(Some(frame)) = frames.next() {
+                let function = frame
+                    .function
+                    .as_ref()
+                    .and_then(|function| function.demangle().ok())
+                    .map(|name| name.into_owned())
+                    .or_else(|| self.symbol_name(address).map(str::to_string))
+                    .unwrap_or_else(|| "??".to_string());
+                let location = match frame.location {
+                    Some(addr2line::Location {
+                        file: Some(file),
+                        line,
+                        ..
+                    }) => format!("{}:{}", file, line.unwrap_or(0)),
+                    _ => "??:?".to_string(),
+                };
+                descriptions.push(format!("{} at {}", function, location));
+            }
+        }
+        if descriptions.is_empty() {
+            if let Some(name) = self.symbol_name(address) {
+                descriptions.push(format!("{} at ??:?", name));
+            }
+        }
+        descriptions
+    }
+
+    // Annotated lines for the program counters found in a line of monitor output.
+    pub fn decode(&self, line: &str) -> Vec<Line> {
+        let mut lines = Vec::new();
+        let mut decoded = Vec::new();
+        for found in self.address_regex.find_iter(line) {
+            let Ok(address) = u64::from_str_radix(&found.as_str()[2..], 16) else {
+                continue;
+            };
+            if decoded.contains(&address) || !self.is_code(address) {
+                continue;
+            }
+            decoded.push(address);
+
+            for (index, description) in self.lookup(address).into_iter().enumerate() {
+                let text = if index == 0 {
+                    format!("{}: {}", found.as_str(), description)
+                } else {
+                    format!("{:>10}  (inlined by) {}", "", description)
+                };
+                lines.push(Line::styled(&text, ANNOTATION_STYLE));
+            }
+        }
+        lines
+    }
+}
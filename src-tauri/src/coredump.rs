@@ -0,0 +1,885 @@
+// Core dumps written by ESP-IDF on a crash, either to the coredump partition
+// or base64 encoded to the console. The dump is an ELF core file with a note
+// per task holding its registers and load segments with the task stacks.
+
+use addr2line::object::elf::{FileHeader32, EM_RISCV, EM_XTENSA, NT_PRSTATUS, PT_LOAD};
+use addr2line::object::read::elf::{FileHeader, ProgramHeader};
+use addr2line::object::Endianness;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use tauri::Window;
+
+use crate::app_state::CancellationToken;
+use crate::backtrace::Symbols;
+use crate::device_error::{report_error, DeviceError};
+use crate::flash_options::FlashOptions;
+use crate::flasher::{read_flash_data, read_partition_table};
+use crate::partition_table::PartitionEntry;
+
+// Length, version, number of tasks, TCB size and number of memory segments
+// precede the ELF file, newer versions add fields after them
+const HEADER_SIZE: usize = 20;
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+// The ELF file starts within this many bytes of the dump
+const MAX_HEADER_SIZE: usize = 64;
+// Erased flash, the partition holds no dump
+const EMPTY_WORD: u32 = 0xFFFFFFFF;
+
+// Markers around the base64 dump printed to the console
+const DUMP_START: &str = "CORE DUMP START";
+const DUMP_END: &str = "CORE DUMP END";
+
+// Notes added by ESP-IDF besides the prstatus notes of the tasks
+const EXTRA_INFO_NOTE: &[u8] = b"EXTRA_INFO";
+const DUMP_INFO_NOTE: &[u8] = b"ESP_CORE_DUMP_INFO";
+// prstatus holds the TCB address in pr_pid, the registers follow the structure
+const PRSTATUS_PID_OFFSET: usize = 24;
+const PRSTATUS_SIZE: usize = 72;
+// Xtensa keeps the address registers after the special and reserved registers
+const XTENSA_SPECIAL_REGISTERS: [&str; 8] = [
+    "pc",
+    "ps",
+    "lbeg",
+    "lend",
+    "lcount",
+    "sar",
+    "windowstart",
+    "windowbase",
+];
+const XTENSA_AR_INDEX: usize = 64;
+const XTENSA_AR_COUNT: usize = 16;
+const RISCV_REGISTERS: [&str; 32] = [
+    "pc", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5",
+    "t6",
+];
+const RISCV_EXTRA_REGISTERS: [&str; 4] = ["mstatus", "mtvec", "mcause", "mtval"];
+// Stop walking corrupted stacks at some point
+const MAX_BACKTRACE_DEPTH: usize = 64;
+
+#[derive(Clone, Copy)]
+enum Architecture {
+    Xtensa,
+    RiscV,
+}
+
+impl Architecture {
+    fn from_machine(machine: u16) -> Option<Self> {
+        match machine {
+            EM_XTENSA => Some(Architecture::Xtensa),
+            EM_RISCV => Some(Architecture::RiscV),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Architecture::Xtensa => "Xtensa",
+            Architecture::RiscV => "RISC-V",
+        }
+    }
+
+    fn registers(self, words: &[u32]) -> Vec<Register> {
+        let named = |names: &[&str], words: &[u32]| {
+            names
+                .iter()
+                .zip(words)
+                .map(|(name, value)| Register::new(name, *value))
+                .collect::<Vec<_>>()
+        };
+        match self {
+            Architecture::Xtensa => {
+                let mut registers = named(&XTENSA_SPECIAL_REGISTERS, words);
+                let ar = words.iter().skip(XTENSA_AR_INDEX).take(XTENSA_AR_COUNT);
+                for (index, value) in ar.enumerate() {
+                    registers.push(Register::new(&format!("a{}", index), *value));
+                }
+                registers
+            }
+            Architecture::RiscV => named(&RISCV_REGISTERS, words),
+        }
+    }
+
+    // Registers describing the exception, the words following the crashed TCB
+    // in the EXTRA_INFO note.
+    fn exception_registers(self, words: &[u32]) -> Vec<Register> {
+        match self {
+            // Pairs of register number and value, unused entries are zero
+            Architecture::Xtensa => words
+                .chunks_exact(2)
+                .filter_map(|pair| {
+                    let name = match pair[0] {
+                        177..=183 => format!("epc{}", pair[0] - 176),
+                        194..=199 => format!("eps{}", pair[0] - 192),
+                        232 => "exccause".to_string(),
+                        238 => "excvaddr".to_string(),
+                        _ => return None,
+                    };
+                    Some(Register::new(&name, pair[1]))
+                })
+                .collect(),
+            Architecture::RiscV => RISCV_EXTRA_REGISTERS
+                .iter()
+                .zip(words)
+                .map(|(name, value)| Register::new(name, *value))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct Register {
+    name: String,
+    value: u32,
+}
+
+impl Register {
+    fn new(name: &str, value: u32) -> Self {
+        Register {
+            name: name.to_string(),
+            value,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct Frame {
+    pc: u32,
+    // Function and source location, followed by the functions it was inlined into
+    functions: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct CoreDumpTask {
+    tcb: u32,
+    crashed: bool,
+    registers: Vec<Register>,
+    backtrace: Vec<Frame>,
+}
+
+#[derive(serde::Serialize)]
+pub struct CoreDump {
+    architecture: &'static str,
+    version: String,
+    exception: Vec<Register>,
+    // SHA-256 prefix of the application which crashed
+    app_sha256: Option<String>,
+    // Whether the supplied ELF is that application, None without ELF or hash
+    elf_matches: Option<bool>,
+    tasks: Vec<CoreDumpTask>,
+}
+
+fn word(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn words(data: &[u8]) -> Vec<u32> {
+    data.chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect()
+}
+
+fn find_value(registers: &[Register], name: &str) -> u32 {
+    registers
+        .iter()
+        .find(|register| register.name == name)
+        .map_or(0, |register| register.value)
+}
+
+// Memory of the chip saved in the load segments, mostly the task stacks.
+struct Memory<'a> {
+    segments: Vec<(u32, &'a [u8])>,
+}
+
+impl Memory<'_> {
+    fn read(&self, address: u32) -> Option<u32> {
+        self.segments.iter().find_map(|(start, data)| {
+            let offset = address.checked_sub(*start)? as usize;
+            word(data, offset)
+        })
+    }
+}
+
+// Walk the windowed ABI frames the same way the panic handler of ESP-IDF does,
+// the caller's a0 and a1 are spilled below the stack pointer of each frame.
+fn xtensa_backtrace(registers: &[Register], memory: &Memory) -> Vec<u32> {
+    let mut pcs = vec![find_value(registers, "pc")];
+    let mut next_pc = find_value(registers, "a0");
+    let mut sp = find_value(registers, "a1");
+
+    while next_pc != 0 && pcs.len() < MAX_BACKTRACE_DEPTH {
+        // The top bits of a return address hold the window size of the call,
+        // the address of the call instruction is 3 bytes before it
+        let pc = match next_pc & 0x80000000 {
+            0 => next_pc,
+            _ => (next_pc & 0x3fffffff) | 0x40000000,
+        };
+        pcs.push(pc.wrapping_sub(3));
+
+        let (Some(caller_pc), Some(caller_sp)) = (
+            memory.read(sp.wrapping_sub(16)),
+            memory.read(sp.wrapping_sub(12)),
+        ) else {
+            break;
+        };
+        // Stacks grow down, anything else means the stack is corrupted
+        if caller_sp <= sp {
+            break;
+        }
+        next_pc = caller_pc;
+        sp = caller_sp;
+    }
+    pcs
+}
+
+// Without frame pointers only the return address of the innermost frame is
+// known on RISC-V.
+fn riscv_backtrace(registers: &[Register]) -> Vec<u32> {
+    [find_value(registers, "pc"), find_value(registers, "ra")]
+        .into_iter()
+        .filter(|pc| *pc != 0)
+        .collect()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+// Parse a dump as stored in the coredump partition. None when the partition is erased.
+pub fn parse_core_dump(
+    data: &[u8],
+    symbols: Option<&Symbols>,
+    elf_sha256: Option<&str>,
+) -> Result<Option<CoreDump>, String> {
+    let (Some(length), Some(version)) = (word(data, 0), word(data, 4)) else {
+        return Err("Core dump is too short".to_string());
+    };
+    if length == EMPTY_WORD {
+        return Ok(None);
+    }
+    let data = &data[..(length as usize).min(data.len())];
+    let elf_start = (HEADER_SIZE..MAX_HEADER_SIZE)
+        .find(|offset| {
+            data.get(*offset..)
+                .map_or(false, |rest| rest.starts_with(ELF_MAGIC))
+        })
+        .ok_or("Only core dumps in the ELF format are supported")?;
+    let elf = &data[elf_start..];
+
+    let invalid = |e: addr2line::object::Error| format!("Invalid core dump: {}", e);
+    let header = FileHeader32::<Endianness>::parse(elf).map_err(invalid)?;
+    let endian = header.endian().map_err(invalid)?;
+    let architecture = Architecture::from_machine(header.e_machine(endian)).ok_or_else(|| {
+        format!(
+            "Unsupported architecture {} in core dump",
+            header.e_machine(endian)
+        )
+    })?;
+
+    let mut memory = Memory {
+        segments: Vec::new(),
+    };
+    let mut tasks = Vec::new();
+    let mut crashed_tcb = None;
+    let mut exception = Vec::new();
+    let mut app_sha256 = None;
+    for segment in header.program_headers(endian, elf).map_err(invalid)? {
+        if segment.p_type(endian) == PT_LOAD {
+            let data = segment
+                .data(endian, elf)
+                .map_err(|_| "Invalid core dump segment".to_string())?;
+            memory.segments.push((segment.p_vaddr(endian), data));
+            continue;
+        }
+        let Some(mut notes) = segment.notes(endian, elf).map_err(invalid)? else {
+            continue;
+        };
+        while let Some(note) = notes.next().map_err(invalid)? {
+            let desc = note.desc();
+            match note.name() {
+                b"CORE" if note.n_type(endian) == NT_PRSTATUS => {
+                    let tcb = word(desc, PRSTATUS_PID_OFFSET).unwrap_or(0);
+                    let registers =
+                        architecture.registers(&words(desc.get(PRSTATUS_SIZE..).unwrap_or(&[])));
+                    tasks.push((tcb, registers));
+                }
+                EXTRA_INFO_NOTE => {
+                    let values = words(desc);
+                    crashed_tcb = values.first().copied();
+                    exception = architecture.exception_registers(values.get(1..).unwrap_or(&[]));
+                }
+                // Version followed by the hex digest of the application ELF
+                DUMP_INFO_NOTE => {
+                    let hash: String = desc
+                        .get(4..)
+                        .unwrap_or(&[])
+                        .iter()
+                        .take_while(|byte| byte.is_ascii_hexdigit())
+                        .map(|byte| *byte as char)
+                        .collect();
+                    app_sha256 = (!hash.is_empty()).then_some(hash);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let tasks = tasks
+        .into_iter()
+        .map(|(tcb, registers)| {
+            let pcs = match architecture {
+                Architecture::Xtensa => xtensa_backtrace(&registers, &memory),
+                Architecture::RiscV => riscv_backtrace(&registers),
+            };
+            let backtrace = pcs
+                .into_iter()
+                .map(|pc| Frame {
+                    pc,
+                    functions: symbols.map_or_else(Vec::new, |symbols| symbols.lookup(pc as u64)),
+                })
+                .collect();
+            CoreDumpTask {
+                tcb,
+                crashed: crashed_tcb == Some(tcb),
+                registers,
+                backtrace,
+            }
+        })
+        .collect();
+
+    let elf_matches = match (&app_sha256, elf_sha256) {
+        (Some(dump), Some(elf)) => Some(elf.starts_with(&dump.to_ascii_lowercase())),
+        _ => None,
+    };
+    Ok(Some(CoreDump {
+        architecture: architecture.name(),
+        version: format!("{}.{}", (version >> 8) & 0xff, version & 0xff),
+        exception,
+        app_sha256,
+        elf_matches,
+        tasks,
+    }))
+}
+
+// Extract the dump from console output, either the whole block printed between
+// the markers or just the base64 text.
+fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+    let mut lines: Vec<&str> = text.lines().collect();
+    if let Some(start) = lines.iter().position(|line| line.contains(DUMP_START)) {
+        lines.drain(..=start);
+        if let Some(end) = lines.iter().position(|line| line.contains(DUMP_END)) {
+            lines.truncate(end);
+        }
+    }
+    let encoded: String = lines
+        .iter()
+        // Lines may start with the timestamp added by the log
+        .filter_map(|line| line.split_whitespace().last())
+        .collect();
+    base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| format!("Invalid base64 core dump: {}", e))
+}
+
+// Symbols and hash of the application ELF, when one is given.
+fn load_elf(elf_path: Option<&str>) -> Result<(Option<Symbols>, Option<String>), DeviceError> {
+    let Some(elf_path) = elf_path.filter(|path| !path.is_empty()) else {
+        return Ok((None, None));
+    };
+    let data = std::fs::read(elf_path).map_err(|e| DeviceError::file_read(elf_path, e))?;
+    let symbols = Symbols::load(elf_path)?;
+    Ok((Some(symbols), Some(sha256_hex(&data))))
+}
+
+fn find_core_dump_partition(partitions: &[PartitionEntry]) -> Result<&PartitionEntry, String> {
+    partitions
+        .iter()
+        .find(|partition| partition.ty == "data" && partition.subtype == "coredump")
+        .ok_or_else(|| "The partition table has no coredump partition".to_string())
+}
+
+// Read the dump from the coredump partition of the device. None when the
+// partition is empty or the read was cancelled.
+pub async fn read_core_dump(
+    window: Window,
+    cancel: CancellationToken,
+    port: String,
+    elf_path: Option<String>,
+    flash_options: FlashOptions,
+) -> Result<Option<CoreDump>, DeviceError> {
+    let (symbols, elf_sha256) =
+        load_elf(elf_path.as_deref()).map_err(|e| report_error(&window, e))?;
+    let table = read_partition_table(port.clone(), flash_options.clone())
+        .await
+        .map_err(|e| report_error(&window, e))?;
+    let partition =
+        find_core_dump_partition(&table.partitions).map_err(|e| report_error(&window, e))?;
+
+    let Some(data) = read_flash_data(
+        &window,
+        &cancel,
+        &port,
+        partition.offset,
+        partition.size,
+        &flash_options,
+    )?
+    else {
+        return Ok(None);
+    };
+    parse_core_dump(&data, symbols.as_ref(), elf_sha256.as_deref())
+        .map_err(|e| report_error(&window, e))
+}
+
+// Decode a dump copied from the monitor output.
+#[tauri::command]
+pub fn decode_core_dump(
+    text: String,
+    elf_path: Option<String>,
+) -> Result<Option<CoreDump>, DeviceError> {
+    let (symbols, elf_sha256) = load_elf(elf_path.as_deref())?;
+    let data = decode_base64(&text)?;
+    Ok(parse_core_dump(
+        &data,
+        symbols.as_ref(),
+        elf_sha256.as_deref(),
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ET_EXEC: u16 = 2;
+    const ET_CORE: u16 = 4;
+    const PT_NOTE: u32 = 4;
+    // Note types used by ESP-IDF
+    const EXTRA_INFO_TYPE: u32 = 677;
+    const DUMP_INFO_TYPE: u32 = 8266;
+    // ELF format, version 1.1
+    const DUMP_VERSION: u32 = 0x0001_0101;
+    const APP_SHA256: &str = "3a6f2ddb1e9f04c8";
+    const STACK_START: u32 = 0x3ffb_0000;
+    const CRASHED_TCB: u32 = 0x3ffb_5a00;
+    const IDLE_TCB: u32 = 0x3ffb_6b00;
+    // Functions in the application ELF
+    const FUNCTIONS: [(&str, u32, u32); 3] = [
+        ("crash_here", 0x400d_0000, 0x18),
+        ("call_crash", 0x400d_0018, 0x18),
+        ("app_main", 0x400d_0030, 0x20),
+    ];
+
+    fn push16(data: &mut Vec<u8>, value: u16) {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn push32(data: &mut Vec<u8>, value: u32) {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn pad4(data: &mut Vec<u8>) {
+        data.resize((data.len() + 3) & !3, 0);
+    }
+
+    fn elf_header(e_type: u16, machine: u16, phnum: u16, shoff: u32, shnum: u16) -> Vec<u8> {
+        let mut data = b"\x7fELF\x01\x01\x01".to_vec();
+        data.resize(16, 0);
+        push16(&mut data, e_type);
+        push16(&mut data, machine);
+        push32(&mut data, 1);
+        push32(&mut data, 0);
+        push32(&mut data, if phnum > 0 { 52 } else { 0 });
+        push32(&mut data, shoff);
+        push32(&mut data, 0);
+        push16(&mut data, 52);
+        push16(&mut data, 32);
+        push16(&mut data, phnum);
+        push16(&mut data, 40);
+        push16(&mut data, shnum);
+        push16(&mut data, shnum.saturating_sub(1));
+        data
+    }
+
+    fn note(name: &str, n_type: u32, desc: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        push32(&mut data, name.len() as u32 + 1);
+        push32(&mut data, desc.len() as u32);
+        push32(&mut data, n_type);
+        data.extend_from_slice(name.as_bytes());
+        data.push(0);
+        pad4(&mut data);
+        data.extend_from_slice(desc);
+        pad4(&mut data);
+        data
+    }
+
+    fn words_to_bytes(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    fn prstatus(tcb: u32, registers: &[u32]) -> Vec<u8> {
+        let mut desc = vec![0; PRSTATUS_SIZE];
+        desc[PRSTATUS_PID_OFFSET..PRSTATUS_PID_OFFSET + 4].copy_from_slice(&tcb.to_le_bytes());
+        desc.extend(words_to_bytes(registers));
+        desc
+    }
+
+    // Registers of an Xtensa task, the rest of the register area is zero
+    fn xtensa_registers(pc: u32, a0: u32, a1: u32) -> Vec<u32> {
+        let mut registers = vec![0; XTENSA_AR_INDEX + XTENSA_AR_COUNT];
+        registers[0] = pc;
+        registers[1] = 0x0006_0020;
+        registers[XTENSA_AR_INDEX] = a0;
+        registers[XTENSA_AR_INDEX + 1] = a1;
+        registers
+    }
+
+    // Dump as written to the coredump partition: the header, a note segment
+    // with the tasks and the ESP-IDF notes, and a load segment with the stacks.
+    fn core_dump(
+        machine: u16,
+        tasks: &[(u32, Vec<u32>)],
+        extra_info: &[u32],
+        stack: &[u32],
+    ) -> Vec<u8> {
+        let mut notes = Vec::new();
+        for (tcb, registers) in tasks {
+            notes.extend(note("CORE", NT_PRSTATUS, &prstatus(*tcb, registers)));
+        }
+        notes.extend(note(
+            "EXTRA_INFO",
+            EXTRA_INFO_TYPE,
+            &words_to_bytes(extra_info),
+        ));
+        let mut dump_info = 2u32.to_le_bytes().to_vec();
+        dump_info.extend_from_slice(APP_SHA256.as_bytes());
+        dump_info.push(0);
+        notes.extend(note("ESP_CORE_DUMP_INFO", DUMP_INFO_TYPE, &dump_info));
+        let stack = words_to_bytes(stack);
+
+        let mut elf = elf_header(ET_CORE, machine, 2, 0, 0);
+        let notes_offset = 52 + 2 * 32;
+        let stack_offset = notes_offset + notes.len() as u32;
+        for (p_type, offset, vaddr, size) in [
+            (PT_NOTE, notes_offset, 0, notes.len() as u32),
+            (PT_LOAD, stack_offset, STACK_START, stack.len() as u32),
+        ] {
+            for value in [p_type, offset, vaddr, vaddr, size, size, 6, 4] {
+                push32(&mut elf, value);
+            }
+        }
+        elf.extend(notes);
+        elf.extend(stack);
+
+        let mut data = Vec::new();
+        push32(&mut data, (HEADER_SIZE + elf.len()) as u32);
+        push32(&mut data, DUMP_VERSION);
+        push32(&mut data, tasks.len() as u32);
+        push32(&mut data, 0x160);
+        push32(&mut data, 1);
+        data.extend(elf);
+        data
+    }
+
+    // Crashed task: crash_here called by call_crash called by app_main, with
+    // the caller's a0 and a1 spilled below each stack pointer. Idle task
+    // without callers.
+    fn xtensa_dump() -> Vec<u8> {
+        let mut stack = vec![0; 0x100];
+        // Frame of crash_here at 0x3ffb0100, returning into call_crash
+        stack[0xf0 / 4] = 0x800d_0040;
+        stack[0xf4 / 4] = 0x3ffb_0200;
+        // Frame of app_main at 0x3ffb0200, the last one
+        stack[0x1f0 / 4] = 0;
+        stack[0x1f4 / 4] = 0x3ffb_0300;
+        let tasks = [
+            (
+                CRASHED_TCB,
+                xtensa_registers(0x400d_0010, 0x800d_0020, 0x3ffb_0100),
+            ),
+            (IDLE_TCB, xtensa_registers(0x400d_0030, 0, 0x3ffb_0380)),
+        ];
+        // Crashed TCB, then EXCCAUSE, EXCVADDR and EPC1 as register number and value
+        let extra_info = [CRASHED_TCB, 232, 28, 238, 0, 177, 0x400d_0010, 0, 0];
+        core_dump(EM_XTENSA, &tasks, &extra_info, &stack)
+    }
+
+    // Application ELF with a symbol table for FUNCTIONS and no debug information.
+    fn application_elf() -> Vec<u8> {
+        let text = vec![0; 0x100];
+        let mut strtab = vec![0];
+        let mut symtab = vec![0; 16];
+        for (name, address, size) in FUNCTIONS {
+            push32(&mut symtab, strtab.len() as u32);
+            push32(&mut symtab, address);
+            push32(&mut symtab, size);
+            // Global function in section 1
+            symtab.extend_from_slice(&[0x12, 0]);
+            push16(&mut symtab, 1);
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+        }
+        let shstrtab = b"\0.text\0.symtab\0.strtab\0.shstrtab\0".to_vec();
+
+        let text_offset = 52;
+        let symtab_offset = text_offset + text.len() as u32;
+        let strtab_offset = symtab_offset + symtab.len() as u32;
+        let shstrtab_offset = strtab_offset + strtab.len() as u32;
+        let mut shoff = shstrtab_offset + shstrtab.len() as u32;
+        shoff = (shoff + 3) & !3;
+
+        let mut elf = elf_header(ET_EXEC, EM_XTENSA, 0, shoff, 5);
+        elf.extend(&text);
+        elf.extend(&symtab);
+        elf.extend(&strtab);
+        elf.extend(&shstrtab);
+        pad4(&mut elf);
+        // Name, type, flags, address, offset, size, link, info, alignment, entry size
+        let sections = [
+            [0; 10],
+            [1, 1, 6, FUNCTIONS[0].1, text_offset, 0x100, 0, 0, 4, 0],
+            [7, 2, 0, 0, symtab_offset, symtab.len() as u32, 3, 1, 4, 16],
+            [15, 3, 0, 0, strtab_offset, strtab.len() as u32, 0, 0, 1, 0],
+            [
+                23,
+                3,
+                0,
+                0,
+                shstrtab_offset,
+                shstrtab.len() as u32,
+                0,
+                0,
+                1,
+                0,
+            ],
+        ];
+        for section in sections {
+            for value in section {
+                push32(&mut elf, value);
+            }
+        }
+        elf
+    }
+
+    fn load_symbols() -> Symbols {
+        let path =
+            std::env::temp_dir().join(format!("esp-workbench-coredump-{}.elf", std::process::id()));
+        std::fs::write(&path, application_elf()).unwrap();
+        let symbols = Symbols::load(path.to_str().unwrap());
+        let _ = std::fs::remove_file(&path);
+        symbols.unwrap()
+    }
+
+    fn backtrace_pcs(task: &CoreDumpTask) -> Vec<u32> {
+        task.backtrace.iter().map(|frame| frame.pc).collect()
+    }
+
+    #[test]
+    fn parse_xtensa_dump() {
+        let dump = parse_core_dump(&xtensa_dump(), None, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(dump.architecture, "Xtensa");
+        assert_eq!(dump.version, "1.1");
+        assert_eq!(dump.app_sha256.as_deref(), Some(APP_SHA256));
+        assert_eq!(dump.elf_matches, None);
+
+        let exception: Vec<(&str, u32)> = dump
+            .exception
+            .iter()
+            .map(|register| (register.name.as_str(), register.value))
+            .collect();
+        assert_eq!(
+            exception,
+            [("exccause", 28), ("excvaddr", 0), ("epc1", 0x400d_0010)]
+        );
+
+        assert_eq!(dump.tasks.len(), 2);
+        let crashed = &dump.tasks[0];
+        assert_eq!(crashed.tcb, CRASHED_TCB);
+        assert!(crashed.crashed);
+        assert_eq!(find_value(&crashed.registers, "pc"), 0x400d_0010);
+        assert_eq!(find_value(&crashed.registers, "ps"), 0x0006_0020);
+        assert_eq!(find_value(&crashed.registers, "a0"), 0x800d_0020);
+        assert_eq!(find_value(&crashed.registers, "a1"), 0x3ffb_0100);
+        assert_eq!(crashed.registers.len(), 8 + 16);
+        // Return addresses point behind the call instruction
+        assert_eq!(
+            backtrace_pcs(crashed),
+            [0x400d_0010, 0x400d_001d, 0x400d_003d]
+        );
+        assert!(crashed
+            .backtrace
+            .iter()
+            .all(|frame| frame.functions.is_empty()));
+
+        let idle = &dump.tasks[1];
+        assert_eq!(idle.tcb, IDLE_TCB);
+        assert!(!idle.crashed);
+        assert_eq!(backtrace_pcs(idle), [0x400d_0030]);
+    }
+
+    #[test]
+    fn decode_backtrace_with_elf() {
+        let symbols = load_symbols();
+        let dump = parse_core_dump(&xtensa_dump(), Some(&symbols), None)
+            .unwrap()
+            .unwrap();
+        let functions: Vec<&str> = dump.tasks[0]
+            .backtrace
+            .iter()
+            .map(|frame| frame.functions[0].as_str())
+            .collect();
+        assert_eq!(
+            functions,
+            [
+                "crash_here at ??:?",
+                "call_crash at ??:?",
+                "app_main at ??:?"
+            ]
+        );
+    }
+
+    #[test]
+    fn elf_hash_compared_with_dump() {
+        let matching = format!("{}{}", APP_SHA256, "0".repeat(48));
+        let other = "0".repeat(64);
+        for (elf_sha256, expected) in [(matching.as_str(), true), (other.as_str(), false)] {
+            let dump = parse_core_dump(&xtensa_dump(), None, Some(elf_sha256))
+                .unwrap()
+                .unwrap();
+            assert_eq!(dump.elf_matches, Some(expected));
+        }
+    }
+
+    #[test]
+    fn corrupted_stack_ends_backtrace() {
+        let mut stack = vec![0; 0x100];
+        // The caller's stack pointer is below the current one
+        stack[0xf0 / 4] = 0x800d_0040;
+        stack[0xf4 / 4] = 0x3ffb_0080;
+        let tasks = [(
+            CRASHED_TCB,
+            xtensa_registers(0x400d_0010, 0x800d_0020, 0x3ffb_0100),
+        )];
+        let dump = parse_core_dump(
+            &core_dump(EM_XTENSA, &tasks, &[CRASHED_TCB], &stack),
+            None,
+            None,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(backtrace_pcs(&dump.tasks[0]), [0x400d_0010, 0x400d_001d]);
+
+        // Stack pointer outside of the saved memory
+        let tasks = [(
+            CRASHED_TCB,
+            xtensa_registers(0x400d_0010, 0x800d_0020, 0x3ff0_0000),
+        )];
+        let dump = parse_core_dump(
+            &core_dump(EM_XTENSA, &tasks, &[CRASHED_TCB], &stack),
+            None,
+            None,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(backtrace_pcs(&dump.tasks[0]), [0x400d_0010, 0x400d_001d]);
+    }
+
+    #[test]
+    fn parse_riscv_dump() {
+        let mut registers = vec![0; 32];
+        registers[0] = 0x4200_1234;
+        registers[1] = 0x4200_5678;
+        registers[2] = 0x3fc8_f000;
+        let tasks = [(CRASHED_TCB, registers)];
+        let extra_info = [CRASHED_TCB, 0x1880, 0x4080_0001, 7, 0x10];
+        let dump = parse_core_dump(
+            &core_dump(EM_RISCV, &tasks, &extra_info, &[0; 4]),
+            None,
+            None,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(dump.architecture, "RISC-V");
+        let exception: Vec<(&str, u32)> = dump
+            .exception
+            .iter()
+            .map(|register| (register.name.as_str(), register.value))
+            .collect();
+        assert_eq!(
+            exception,
+            [
+                ("mstatus", 0x1880),
+                ("mtvec", 0x4080_0001),
+                ("mcause", 7),
+                ("mtval", 0x10)
+            ]
+        );
+        let task = &dump.tasks[0];
+        assert!(task.crashed);
+        assert_eq!(find_value(&task.registers, "sp"), 0x3fc8_f000);
+        assert_eq!(backtrace_pcs(task), [0x4200_1234, 0x4200_5678]);
+    }
+
+    #[test]
+    fn erased_partition_has_no_dump() {
+        assert!(parse_core_dump(&[0xff; 64], None, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_dumps_rejected() {
+        let mut unsupported = xtensa_dump();
+        // e_machine of the ELF header
+        unsupported[HEADER_SIZE + 18] = 3;
+        let mut binary_format = xtensa_dump();
+        binary_format[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(b"\0\0\0\0");
+        let cases = [
+            (vec![1, 2, 3], "too short"),
+            (binary_format, "ELF format"),
+            (unsupported, "Unsupported architecture 3"),
+            (
+                xtensa_dump()[..HEADER_SIZE + 30].to_vec(),
+                "Invalid core dump",
+            ),
+        ];
+        for (data, expected) in cases {
+            let err = parse_core_dump(&data, None, None).err().unwrap();
+            assert!(err.contains(expected), "{}", err);
+        }
+    }
+
+    #[test]
+    fn decode_console_output() {
+        let dump = xtensa_dump();
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&dump);
+        let body: Vec<&str> = encoded
+            .as_bytes()
+            .chunks(64)
+            .map(|chunk| std::str::from_utf8(chunk).unwrap())
+            .collect();
+
+        // As printed by ESP-IDF, with the timestamps of the monitor log
+        let mut console = vec![
+            "[2024-03-01 10:00:00.000] I (1234) esp_core_dump_uart: Print core dump to uart..."
+                .to_string(),
+            "[2024-03-01 10:00:00.001] ================= CORE DUMP START ================="
+                .to_string(),
+        ];
+        console.extend(
+            body.iter()
+                .map(|line| format!("[2024-03-01 10:00:00.002] {}", line)),
+        );
+        console.push(
+            "[2024-03-01 10:00:00.003] ================= CORE DUMP END ==================="
+                .to_string(),
+        );
+        console.push("[2024-03-01 10:00:00.004] I (1300) esp_core_dump_uart: Core dump has been written to uart.".to_string());
+        assert_eq!(decode_base64(&console.join("\n")).unwrap(), dump);
+
+        // Only the base64 text
+        assert_eq!(decode_base64(&body.join("\r\n")).unwrap(), dump);
+
+        assert!(decode_base64("not base64!").is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use espflash::error::{ConnectionError, Error as EspflashError};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::error::Error as StdError;
+use std::io;
+use tauri::Window;
+
+// Errors of the flasher and the monitor. They are reported to the frontend with
+// a code, a human readable message and a suggestion how to fix the problem.
+#[derive(Debug, thiserror::Error)]
+pub enum DeviceError {
+    #[error("Serial port {0} not found")]
+    PortNotFound(String),
+    #[error("Serial port {0} is not a USB port")]
+    NotUsbPort(String),
+    #[error("Serial port {0} is busy")]
+    PortBusy(String),
+    #[error("Permission denied to open serial port {0}")]
+    PermissionDenied(String),
+    #[error("Failed to connect to the chip: {0}")]
+    ConnectionFailed(String),
+    #[error("Serial communication failed: {0}")]
+    Serial(String),
+    #[error("Flash operation failed: {0}")]
+    Flash(String),
+    #[error("Failed to read {path}: {message}")]
+    FileRead { path: String, message: String },
+    #[error("Failed to write {path}: {message}")]
+    FileWrite { path: String, message: String },
+    #[error("Flash verification failed")]
+    VerifyFailed,
+    #[error("{0}")]
+    InvalidInput(String),
+}
+
+#[derive(Clone, serde::Serialize)]
+struct ErrorPayload {
+    pct: String,
+    code: &'static str,
+    remedy: Option<&'static str>,
+}
+
+// Message of the error followed by the messages of all its sources.
+fn describe(error: &dyn StdError) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+impl DeviceError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            DeviceError::PortNotFound(_) => "port_not_found",
+            DeviceError::NotUsbPort(_) => "not_usb_port",
+            DeviceError::PortBusy(_) => "port_busy",
+            DeviceError::PermissionDenied(_) => "permission_denied",
+            DeviceError::ConnectionFailed(_) => "connection_failed",
+            DeviceError::Serial(_) => "serial_error",
+            DeviceError::Flash(_) => "flash_error",
+            DeviceError::FileRead { .. } => "file_read_error",
+            DeviceError::FileWrite { .. } => "file_write_error",
+            DeviceError::VerifyFailed => "verify_failed",
+            DeviceError::InvalidInput(_) => "invalid_input",
+        }
+    }
+
+    pub fn remedy(&self) -> Option<&'static str> {
+        match self {
+            DeviceError::PortNotFound(_) => {
+                Some("Check that the board is connected and select the port again")
+            }
+            DeviceError::NotUsbPort(_) => Some("Select the USB serial port of the board"),
+            DeviceError::PortBusy(_) => {
+                Some("Close the monitor or any other program using the port")
+            }
+            DeviceError::PermissionDenied(_) => Some(
+                "Add your user to the dialout group (sudo usermod -a -G dialout $USER) and log in again",
+            ),
+            DeviceError::ConnectionFailed(_) => Some(
+                "Hold the BOOT button while connecting, or try another reset mode or a lower baud rate",
+            ),
+            DeviceError::Serial(_) => Some("Check the USB cable and reconnect the board"),
+            DeviceError::Flash(_) => Some("Reset the board and try again"),
+            DeviceError::FileRead { .. } | DeviceError::FileWrite { .. } => {
+                Some("Check that the path exists and is accessible")
+            }
+            DeviceError::VerifyFailed => Some("Erase the flash and flash the images again"),
+            DeviceError::InvalidInput(_) => None,
+        }
+    }
+
+    // Errors of opening or using a serial port.
+    pub fn from_serial(port: &str, error: serialport::Error) -> Self {
+        match error.kind() {
+            serialport::ErrorKind::NoDevice => DeviceError::PortNotFound(port.to_string()),
+            serialport::ErrorKind::Io(kind) => {
+                DeviceError::from_io(port, io::Error::new(kind, error))
+            }
+            // EBUSY has no io::ErrorKind of its own
+            _ if error.description.to_lowercase().contains("busy") => {
+                DeviceError::PortBusy(port.to_string())
+            }
+            _ => DeviceError::Serial(error.to_string()),
+        }
+    }
+
+    pub fn from_io(port: &str, error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::NotFound => DeviceError::PortNotFound(port.to_string()),
+            // Windows denies access to ports opened by another program
+            io::ErrorKind::PermissionDenied if cfg!(windows) => {
+                DeviceError::PortBusy(port.to_string())
+            }
+            io::ErrorKind::PermissionDenied => DeviceError::PermissionDenied(port.to_string()),
+            _ => DeviceError::Serial(error.to_string()),
+        }
+    }
+
+    pub fn from_espflash(port: &str, error: EspflashError) -> Self {
+        match error {
+            EspflashError::Connection(ConnectionError::Serial(error)) => {
+                DeviceError::from_serial(port, error)
+            }
+            EspflashError::Connection(ConnectionError::DeviceNotFound)
+            | EspflashError::SerialNotFound(_) => DeviceError::PortNotFound(port.to_string()),
+            EspflashError::Connection(ref cause) => match cause {
+                ConnectionError::ConnectionFailed | ConnectionError::Timeout(_) => {
+                    DeviceError::ConnectionFailed(describe(&error))
+                }
+                _ => DeviceError::Serial(describe(&error)),
+            },
+            _ => DeviceError::Flash(describe(&error)),
+        }
+    }
+
+    // Interface::new wraps the espflash error into a report.
+    pub fn from_interface(port: &str, report: miette::Report) -> Self {
+        match report.downcast::<EspflashError>() {
+            Ok(error) => DeviceError::from_espflash(port, error),
+            Err(report) => DeviceError::Serial(report.to_string()),
+        }
+    }
+
+    pub fn file_read(path: impl AsRef<std::path::Path>, error: io::Error) -> Self {
+        DeviceError::FileRead {
+            path: path.as_ref().display().to_string(),
+            message: error.to_string(),
+        }
+    }
+
+    pub fn file_write(path: impl AsRef<std::path::Path>, error: io::Error) -> Self {
+        DeviceError::FileWrite {
+            path: path.as_ref().display().to_string(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<String> for DeviceError {
+    fn from(message: String) -> Self {
+        DeviceError::InvalidInput(message)
+    }
+}
+
+impl Serialize for DeviceError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("DeviceError", 3)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("remedy", &self.remedy())?;
+        state.end()
+    }
+}
+
+// Show the error in the frontend, the payload stays compatible with plain error events.
+pub fn emit_device_error(window: &Window, error: &DeviceError) {
+    let payload = ErrorPayload {
+        pct: format!("Error: {}", error),
+        code: error.code(),
+        remedy: error.remedy(),
+    };
+    let _ = window.emit("error", payload);
+}
+
+// Emit the error and hand it back so it can be returned.
+pub fn report_error(window: &Window, error: impl Into<DeviceError>) -> DeviceError {
+    let error = error.into();
+    emit_device_error(window, &error);
+    error
+}
@@ -1,64 +1,485 @@
-use std::path::Path;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+use reqwest::header::{HeaderMap, CONTENT_RANGE, RANGE};
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
 use tokio::io::AsyncWriteExt;
 
 use tauri::{Manager, Window};
 
-use crate::app_state::{AppState, BuilderState};
+use crate::app_state::CancellationToken;
+use crate::network::load_network_settings;
 use log::info;
-use std::sync::Mutex;
 
 const PROGRESS_EVENT: &str = "progress";
+// Limit the rate of progress events, chunks arrive every few milliseconds
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+const PART_EXTENSION: &str = "part";
 
 #[derive(Clone, serde::Serialize)]
 struct Payload {
     pct: String,
+    bytes: u64,
+    total: Option<u64>,
+    // Bytes per second received by this request
+    rate: u64,
+    // Estimated seconds until the download finishes
+    eta: Option<u64>,
 }
 
-fn is_abort_state(app: tauri::AppHandle) -> bool {
-    let state_mutex = app.state::<Mutex<AppState>>();
-    let state = state_mutex.lock().unwrap();
-    matches!(state.builder, BuilderState::Abort)
+// Incomplete downloads are kept next to the destination until they finish.
+fn part_path(dest_path: &Path) -> PathBuf {
+    let mut name = dest_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(PART_EXTENSION);
+    dest_path.with_file_name(name)
+}
+
+// Total size from "Content-Range: bytes 100-999/1000".
+fn content_range_total(headers: &HeaderMap) -> Option<u64> {
+    let range = headers.get(CONTENT_RANGE)?.to_str().ok()?;
+    range.rsplit('/').next()?.parse().ok()
+}
+
+pub fn file_sha256(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let count = file.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..count]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn emit_progress(
+    window: &Window,
+    downloaded: u64,
+    total: Option<u64>,
+    received: u64,
+    start: Instant,
+) {
+    let elapsed = start.elapsed().as_secs_f64();
+    let rate = match elapsed > 0.0 {
+        true => (received as f64 / elapsed) as u64,
+        false => 0,
+    };
+    let eta = total
+        .filter(|_| rate > 0)
+        .map(|total| total.saturating_sub(downloaded) / rate);
+    let pct = match total {
+        Some(total) if total > 0 => format!("{:.1}%", downloaded as f64 / total as f64 * 100.0),
+        _ => format!("{} bytes", downloaded),
+    };
+    let payload = Payload {
+        pct,
+        bytes: downloaded,
+        total,
+        rate,
+        eta,
+    };
+    let _ = window.emit(PROGRESS_EVENT, payload);
 }
 
 pub async fn download_file(
-    _window: Window,
-    app: tauri::AppHandle,
+    window: Window,
+    cancel: CancellationToken,
     url: &str,
     dest_path: &Path,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    info!("Downloading file from {} to {}", url, dest_path.display());
-    let total_size = {
-        let resp = reqwest::get(url).await?;
-        resp.content_length()
-            .ok_or("unable to get content length")?
-    };
+    download_verified_file(window, cancel, url, dest_path, None).await
+}
 
-    let request = reqwest::get(url);
-    let mut response = request.await?;
+// Download into a .part file which is resumed with a Range request when it
+// exists, and moved to the destination once complete and matching the
+// SHA-256 checksum. Cancelled downloads keep the .part file for the next attempt.
+pub async fn download_verified_file(
+    window: Window,
+    cancel: CancellationToken,
+    url: &str,
+    dest_path: &Path,
+    sha256: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = load_network_settings(&window.app_handle()).client()?;
+    fetch_file(
+        &client,
+        &cancel,
+        url,
+        dest_path,
+        sha256,
+        |downloaded, total, received, start| {
+            emit_progress(&window, downloaded, total, received, start)
+        },
+    )
+    .await
+}
 
+// Progress is reported with the bytes of the file, its total size, the bytes
+// received by this request and the start of the request.
+async fn fetch_file(
+    client: &reqwest::Client,
+    cancel: &CancellationToken,
+    url: &str,
+    dest_path: &Path,
+    sha256: Option<&str>,
+    mut progress: impl FnMut(u64, Option<u64>, u64, Instant),
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Downloading file from {} to {}", url, dest_path.display());
     if let Some(parent) = dest_path.parent() {
         std::fs::create_dir_all(parent)?; // Ensure the directory exists
     }
+    let part_path = part_path(dest_path);
+    let mut offset = tokio::fs::metadata(&part_path)
+        .await
+        .map_or(0, |metadata| metadata.len());
+
+    let mut request = client.get(url);
+    if offset > 0 {
+        info!("Resuming download at {} bytes", offset);
+        request = request.header(RANGE, format!("bytes={}-", offset));
+    }
+    let mut response = request.send().await?;
+
+    // The part file is already complete, or no longer matches the file on the server
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        offset = 0;
+        response = client.get(url).send().await?;
+    }
+    let mut response = response.error_for_status()?;
+
+    let total = match response.status() {
+        StatusCode::PARTIAL_CONTENT => content_range_total(response.headers()),
+        _ => {
+            // The server ignored the range, start over
+            offset = 0;
+            response.content_length()
+        }
+    };
 
     let mut dest = tokio::fs::OpenOptions::new()
         .create(true)
-        .append(true)
-        .open(&dest_path)
+        .write(true)
+        .append(offset > 0)
+        .truncate(offset == 0)
+        .open(&part_path)
         .await?;
 
-    let mut downloaded: u64 = 0;
-
+    let start = Instant::now();
+    let mut last_progress = start;
+    let mut downloaded = offset;
     while let Some(chunk) = response.chunk().await? {
         dest.write_all(&chunk).await?;
         downloaded += chunk.len() as u64;
-        let percentage = downloaded as f64 / total_size as f64 * 100.0;
-        info!("Download progress: {:.1}%", percentage);
-        if is_abort_state(app.clone()) {
-            info!("Download aborted at: {:.1}%", percentage);
-            break;
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            last_progress = Instant::now();
+            progress(downloaded, total, downloaded - offset, start);
         }
+        if cancel.is_cancelled() {
+            dest.flush().await?;
+            info!("Download aborted at {} bytes", downloaded);
+            return Err("Download aborted".into());
+        }
+    }
+    dest.flush().await?;
+    drop(dest);
+    progress(downloaded, total, downloaded - offset, start);
+
+    if let Some(total) = total.filter(|total| downloaded != *total) {
+        return Err(format!("Download incomplete: {} of {} bytes", downloaded, total).into());
     }
 
+    if let Some(expected) = sha256 {
+        let actual = file_sha256(&part_path)?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            // Resuming would not help, the data is wrong
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return Err(format!(
+                "Checksum mismatch for {}: expected {}, got {}",
+                url, expected, actual
+            )
+            .into());
+        }
+    }
+
+    tokio::fs::rename(&part_path, dest_path).await?;
+    info!("Downloaded {} bytes to {}", downloaded, dest_path.display());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    // Directory for the downloads of a test, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("esp-workbench-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // HTTP server on localhost serving `body`, Range requests are answered
+    // when `ranges` is set. The start of the range of each request is recorded.
+    struct TestServer {
+        url: String,
+        requests: Arc<Mutex<Vec<Option<usize>>>>,
+    }
+
+    impl TestServer {
+        fn start(body: Vec<u8>, ranges: bool) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/esp-idf.zip", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let recorded = requests.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut range = None;
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                            break;
+                        }
+                        if let Some(value) = line.to_ascii_lowercase().strip_prefix("range: bytes=")
+                        {
+                            range = value.trim().trim_end_matches('-').parse::<usize>().ok();
+                        }
+                    }
+                    recorded.lock().unwrap().push(range);
+
+                    let (status, header, content) = match range.filter(|_| ranges) {
+                        Some(start) if start >= body.len() => (
+                            "416 Range Not Satisfiable",
+                            format!("Content-Range: bytes */{}\r\n", body.len()),
+                            &body[..0],
+                        ),
+                        Some(start) => (
+                            "206 Partial Content",
+                            format!(
+                                "Content-Range: bytes {}-{}/{}\r\n",
+                                start,
+                                body.len() - 1,
+                                body.len()
+                            ),
+                            &body[start..],
+                        ),
+                        None => ("200 OK", String::new(), &body[..]),
+                    };
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        header,
+                        content.len()
+                    );
+                    let _ = stream.write_all(content);
+                }
+            });
+            TestServer { url, requests }
+        }
+
+        fn requests(&self) -> Vec<Option<usize>> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    fn body() -> Vec<u8> {
+        (0..64 * 1024).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(data))
+    }
+
+    // Download `url` to `dest_path`, returns the result and the last progress
+    // as bytes of the file, total size and bytes received by the request.
+    async fn fetch(
+        url: &str,
+        dest_path: &Path,
+        sha256: Option<&str>,
+        cancel: &CancellationToken,
+    ) -> (bool, Option<(u64, Option<u64>, u64)>) {
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let mut last_progress = None;
+        let result = fetch_file(
+            &client,
+            cancel,
+            url,
+            dest_path,
+            sha256,
+            |downloaded, total, received, _| last_progress = Some((downloaded, total, received)),
+        )
+        .await;
+        (result.is_ok(), last_progress)
+    }
+
+    #[test]
+    fn part_paths() {
+        let cases = [
+            ("esp-idf.zip", "esp-idf.zip.part"),
+            (
+                "/tmp/dist/esp-idf-v5.1.2.zip",
+                "/tmp/dist/esp-idf-v5.1.2.zip.part",
+            ),
+            ("/tmp/dist/xtensa.tar.xz", "/tmp/dist/xtensa.tar.xz.part"),
+            ("rustup-init", "rustup-init.part"),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(part_path(Path::new(path)), PathBuf::from(expected));
+        }
+    }
+
+    #[test]
+    fn content_range_totals() {
+        let cases = [
+            (Some("bytes 100-999/1000"), Some(1000)),
+            (Some("bytes 0-0/1"), Some(1)),
+            (Some("bytes */1000"), Some(1000)),
+            // Unknown total
+            (Some("bytes 0-99/*"), None),
+            (Some("invalid"), None),
+            (None, None),
+        ];
+        for (value, expected) in cases {
+            let mut headers = HeaderMap::new();
+            if let Some(value) = value {
+                headers.insert(CONTENT_RANGE, HeaderValue::from_static(value));
+            }
+            assert_eq!(content_range_total(&headers), expected, "{:?}", value);
+        }
+    }
+
+    #[tokio::test]
+    async fn download_complete_file() {
+        let dir = TempDir::new("download-complete");
+        let server = TestServer::start(body(), true);
+        let dest_path = dir.0.join("esp-idf.zip");
+        let size = body().len() as u64;
+
+        let (ok, progress) =
+            fetch(&server.url, &dest_path, None, &CancellationToken::default()).await;
+
+        assert!(ok);
+        assert_eq!(std::fs::read(&dest_path).unwrap(), body());
+        assert!(!part_path(&dest_path).exists());
+        assert_eq!(server.requests(), vec![None]);
+        assert_eq!(progress, Some((size, Some(size), size)));
+    }
+
+    #[tokio::test]
+    async fn resume_from_part_file() {
+        let dir = TempDir::new("download-resume");
+        let server = TestServer::start(body(), true);
+        let dest_path = dir.0.join("esp-idf.zip");
+        std::fs::write(part_path(&dest_path), &body()[..1000]).unwrap();
+        let size = body().len() as u64;
+
+        let (ok, progress) =
+            fetch(&server.url, &dest_path, None, &CancellationToken::default()).await;
+
+        assert!(ok);
+        assert_eq!(std::fs::read(&dest_path).unwrap(), body());
+        assert!(!part_path(&dest_path).exists());
+        // 206, only the rest is received
+        assert_eq!(server.requests(), vec![Some(1000)]);
+        assert_eq!(progress, Some((size, Some(size), size - 1000)));
+    }
+
+    #[tokio::test]
+    async fn restart_when_range_is_ignored() {
+        let dir = TempDir::new("download-no-range");
+        let server = TestServer::start(body(), false);
+        let dest_path = dir.0.join("esp-idf.zip");
+        std::fs::write(part_path(&dest_path), [0xFF; 1000]).unwrap();
+
+        let (ok, _) = fetch(&server.url, &dest_path, None, &CancellationToken::default()).await;
+
+        // 200 to a Range request, the part file is overwritten
+        assert!(ok);
+        assert_eq!(std::fs::read(&dest_path).unwrap(), body());
+        assert_eq!(server.requests(), vec![Some(1000)]);
+    }
+
+    #[tokio::test]
+    async fn restart_when_range_is_not_satisfiable() {
+        let dir = TempDir::new("download-416");
+        let server = TestServer::start(body(), true);
+        let dest_path = dir.0.join("esp-idf.zip");
+        let mut part = body();
+        part.extend_from_slice(&[0xFF; 10]);
+        std::fs::write(part_path(&dest_path), &part).unwrap();
+
+        let (ok, _) = fetch(&server.url, &dest_path, None, &CancellationToken::default()).await;
+
+        // 416, downloaded again without a range
+        assert!(ok);
+        assert_eq!(std::fs::read(&dest_path).unwrap(), body());
+        assert_eq!(server.requests(), vec![Some(part.len()), None]);
+    }
+
+    #[tokio::test]
+    async fn keep_part_file_on_abort() {
+        let dir = TempDir::new("download-abort");
+        let server = TestServer::start(body(), true);
+        let dest_path = dir.0.join("esp-idf.zip");
+        let cancel = CancellationToken::default();
+        cancel.cancel();
+
+        let (ok, _) = fetch(&server.url, &dest_path, None, &cancel).await;
+
+        assert!(!ok);
+        assert!(!dest_path.exists());
+        let part = std::fs::read(part_path(&dest_path)).unwrap();
+        assert!(!part.is_empty());
+        assert!(body().starts_with(&part));
+    }
+
+    #[tokio::test]
+    async fn verify_checksum() {
+        let checksum = sha256(&body());
+        let cases = [
+            (checksum.clone(), true),
+            (checksum.to_uppercase(), true),
+            (sha256(b"other"), false),
+        ];
+        for (index, (checksum, valid)) in cases.into_iter().enumerate() {
+            let dir = TempDir::new(&format!("download-checksum-{}", index));
+            let server = TestServer::start(body(), true);
+            let dest_path = dir.0.join("esp-idf.zip");
+
+            let (ok, _) = fetch(
+                &server.url,
+                &dest_path,
+                Some(&checksum),
+                &CancellationToken::default(),
+            )
+            .await;
+
+            assert_eq!(ok, valid, "{}", checksum);
+            assert_eq!(dest_path.exists(), valid);
+            // A mismatching part file is not resumed
+            assert!(!part_path(&dest_path).exists());
+        }
+    }
+}
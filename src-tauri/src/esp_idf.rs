@@ -1,10 +1,12 @@
 use log::info;
 
-use crate::download::download_file;
+use crate::download::download_verified_file;
 use std::path::Path;
-use tauri::Window;
+use tauri::{Manager, Window};
 
+use crate::app_state::CancellationToken;
 use crate::external_command::run_external_command_with_progress;
+use crate::network::load_network_settings;
 
 #[derive(Clone, serde::Serialize)]
 struct Payload {
@@ -19,36 +21,30 @@ const INSTALL_SCRIPT_NAME: &str = "install.sh";
 #[cfg(windows)]
 const INSTALL_SCRIPT_NAME: &str = "install.bat";
 
-pub fn run_install_script(
+// `extra_envs` are passed to the script besides the network settings.
+pub async fn run_install_script(
     window: Window,
-    app: tauri::AppHandle,
+    cancel: CancellationToken,
     esp_idf_path: String,
+    extra_envs: Vec<(String, String)>,
 ) -> Result<String, ()> {
     let file_path = Path::new(&esp_idf_path).join(INSTALL_SCRIPT_NAME);
     info!("Running install script: {:?}", file_path);
+    let mut envs = load_network_settings(&window.app_handle()).command_env();
+    envs.extend(extra_envs);
 
     #[cfg(unix)]
     {
         let args = vec![file_path.to_str().unwrap()];
-        run_external_command_with_progress(
-            window.clone(),
-            app.clone(),
-            "bash",
-            &args,
-            PROGRESS_EVENT,
-        );
+        run_external_command_with_progress(window, cancel, "bash", &args, &envs, PROGRESS_EVENT)
+            .await?;
     }
 
     #[cfg(windows)]
     {
         let args = vec!["/c", file_path.to_str().unwrap()];
-        run_external_command_with_progress(
-            window.clone(),
-            app.clone(),
-            "cmd",
-            &args,
-            PROGRESS_EVENT,
-        );
+        run_external_command_with_progress(window, cancel, "cmd", &args, &envs, PROGRESS_EVENT)
+            .await?;
     }
 
     Ok("Success".to_string())
@@ -56,14 +52,15 @@ pub fn run_install_script(
 
 pub async fn download_esp_idf(
     window: Window,
-    app: tauri::AppHandle,
+    cancel: CancellationToken,
     version: String,
     dest_path: String,
-) -> Result<(), ()> {
-    let url = format!(
-        "https://github.com/espressif/esp-idf/releases/download/{}/esp-idf-{}.zip",
+    sha256: Option<String>,
+) -> Result<(), String> {
+    let url = load_network_settings(&window.app_handle()).github_asset_url(&format!(
+        "espressif/esp-idf/releases/download/{}/esp-idf-{}.zip",
         version, version
-    );
+    ));
     info!("Downloading ESP-IDF from {}", url);
     let dest_path = Path::new(&dest_path);
 
@@ -83,23 +80,27 @@ pub async fn download_esp_idf(
         };
 
         if is_file_corrupted {
-            tokio::fs::remove_file(&dest_path).await.unwrap();
+            tokio::fs::remove_file(&dest_path)
+                .await
+                .map_err(|err| format!("Failed to remove {}: {}", dest_path.display(), err))?;
         }
     }
 
     // Ensure parent directory exists
     if let Some(parent_path) = dest_path.parent() {
-        tokio::fs::create_dir_all(parent_path).await.unwrap();
+        tokio::fs::create_dir_all(parent_path)
+            .await
+            .map_err(|err| format!("Failed to create {}: {}", parent_path.display(), err))?;
     }
 
-    match download_file(window, app, &url, dest_path).await {
+    match download_verified_file(window, cancel, &url, dest_path, sha256.as_deref()).await {
         Ok(_) => {
             info!("ESP-IDF downloaded successfully");
             Ok(())
         }
         Err(err) => {
             info!("Failed to download ESP-IDF: {}", err);
-            Err(())
+            Err(format!("Failed to download ESP-IDF: {}", err))
         }
     }
 }
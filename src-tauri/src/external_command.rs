@@ -1,26 +1,21 @@
 use std::process::Stdio;
-use std::sync::Mutex;
 
-use crate::app_state::{AppState, BuilderState};
-use tauri::Manager;
+use crate::app_state::CancellationToken;
 use tauri::Window;
 
 use log::info;
 
-fn is_abort_state(app: tauri::AppHandle) -> bool {
-    let state_mutex = app.state::<Mutex<AppState>>();
-    let state = state_mutex.lock().unwrap();
-    matches!(state.builder, BuilderState::Abort)
-}
-
 use tokio::io::AsyncBufReadExt;
 use tokio::process::Command;
 
+// `envs` are added to the environment of the command, e.g. the proxy for
+// installers which download on their own.
 pub async fn run_external_command_with_progress(
     _window: Window,
-    app: tauri::AppHandle,
+    cancel: CancellationToken,
     cmd_name: &str,
     cmd_args: &[&str],
+    envs: &[(String, String)],
     _progress_event: &str,
 ) -> Result<String, ()> {
     let cmd_name_owned = cmd_name.to_string();
@@ -30,6 +25,7 @@ pub async fn run_external_command_with_progress(
 
     let child_result = Command::new(&cmd_name_owned)
         .args(&cmd_args_owned)
+        .envs(envs.iter().map(|(name, value)| (name, value)))
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn();
@@ -81,9 +77,11 @@ pub async fn run_external_command_with_progress(
                 }
             },
             _ = tokio::time::sleep(poll_interval) => {
-                if is_abort_state(app.clone()) {
+                if cancel.is_cancelled() {
                     info!("Aborting command due to external signal.");
-                    let _ = child.kill();
+                    if let Err(err) = child.kill().await {
+                        info!("Failed to kill child process: {:?}", err);
+                    }
                     return Err(());
                 }
             }
@@ -0,0 +1,119 @@
+use crate::flasher::FlashSettings;
+use crate::flasher_args::FlashSettingsArgs;
+use crate::settings::{load_settings, save_settings};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tauri::AppHandle;
+
+const FLASH_OPTIONS_FILE: &str = "flash_options.json";
+// espflash selects the reset sequence by the USB PID of the port
+pub const USB_SERIAL_JTAG_PID: u16 = 0x1001;
+const CLASSIC_RESET_PID: u16 = 0;
+
+// Reset sequence used to enter the bootloader.
+// auto: chosen by espflash from the USB PID of the port.
+// classic: DTR/RTS sequence of USB-UART bridges with the usual auto-reset circuit.
+// usb_jtag: sequence of the built-in USB-Serial-JTAG peripheral.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResetBefore {
+    #[default]
+    Auto,
+    Classic,
+    UsbJtag,
+}
+
+// What happens with the chip when the operation is done.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResetAfter {
+    #[default]
+    HardReset,
+    NoReset,
+}
+
+impl ResetBefore {
+    // USB PID to report to espflash so it picks the requested reset sequence.
+    pub fn reset_pid(self, pid: u16) -> u16 {
+        match self {
+            ResetBefore::Auto => pid,
+            ResetBefore::Classic if pid == USB_SERIAL_JTAG_PID => CLASSIC_RESET_PID,
+            ResetBefore::Classic => pid,
+            ResetBefore::UsbJtag => USB_SERIAL_JTAG_PID,
+        }
+    }
+}
+
+// Connection options of a port, flash_mode/flash_size/flash_freq override the image header.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FlashOptions {
+    pub baud: Option<u32>,
+    pub before: ResetBefore,
+    pub after: ResetAfter,
+    pub use_stub: bool,
+    pub flash_mode: Option<String>,
+    pub flash_freq: Option<String>,
+    pub flash_size: Option<String>,
+}
+
+impl FlashOptions {
+    pub fn flash_settings(&self) -> Result<FlashSettings, String> {
+        FlashSettings::from_args(&FlashSettingsArgs {
+            flash_mode: self.flash_mode.clone(),
+            flash_size: self.flash_size.clone(),
+            flash_freq: self.flash_freq.clone(),
+        })
+    }
+}
+
+pub fn load_flash_options(app: &AppHandle, port: &str) -> FlashOptions {
+    let mut options: BTreeMap<String, FlashOptions> = load_settings(app, FLASH_OPTIONS_FILE);
+    options.remove(port).unwrap_or_default()
+}
+
+pub fn save_flash_options(
+    app: &AppHandle,
+    port: &str,
+    options: &FlashOptions,
+) -> Result<(), String> {
+    let mut all_options: BTreeMap<String, FlashOptions> = load_settings(app, FLASH_OPTIONS_FILE);
+    all_options.insert(port.to_string(), options.clone());
+    save_settings(app, FLASH_OPTIONS_FILE, &all_options)
+}
+
+// Options passed along with a command are remembered for the port, otherwise
+// the last options used with the port apply.
+pub fn resolve_flash_options(
+    app: &AppHandle,
+    port: &str,
+    options: Option<FlashOptions>,
+) -> FlashOptions {
+    match options {
+        Some(options) => {
+            if let Err(err) = save_flash_options(app, port, &options) {
+                warn!("Failed to save flash options: {}", err);
+            }
+            options
+        }
+        None => load_flash_options(app, port),
+    }
+}
+
+// Command to get the flash options remembered for a port
+#[tauri::command]
+pub fn get_flash_options(app: AppHandle, port: String) -> FlashOptions {
+    load_flash_options(&app, &port)
+}
+
+// Command to remember flash options for a port
+#[tauri::command]
+pub fn set_flash_options(
+    app: AppHandle,
+    port: String,
+    options: FlashOptions,
+) -> Result<(), String> {
+    options.flash_settings()?;
+    save_flash_options(&app, &port, &options)
+}
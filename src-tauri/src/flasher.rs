@@ -1,60 +1,221 @@
-use espflash::flasher::Flasher;
-use espflash::flasher::ProgressCallbacks;
+use crate::app_state::CancellationToken;
+use crate::device_error::{report_error, DeviceError};
+use crate::flash_options::{FlashOptions, ResetAfter};
+use crate::flasher_args::{load_flasher_args, FlashImage, FlashSettingsArgs};
+use crate::loader::Loader;
+use crate::partition_table::{
+    find_partition, parse_partition_table, validate, PartitionEntry, PARTITION_TABLE_OFFSET,
+    PARTITION_TABLE_SIZE,
+};
+use espflash::connection::{reset_after_flash, Connection};
+use espflash::elf::{ElfFirmwareImage, RomSegment};
+use espflash::flasher::{
+    FlashFrequency, FlashMode, FlashSize, Flasher, ProgressCallbacks, SpiAttachParams,
+};
 use espflash::interface::Interface;
+use espflash::targets::Chip;
+use log::{debug, info};
 use serialport::available_ports;
 use serialport::SerialPortInfo;
-use std::fs::read;
+use serialport::UsbPortInfo;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::fs::{read, File};
 use std::io;
+use std::io::Write;
 use std::path::PathBuf;
-use tauri::AppHandle;
 use tauri::Window;
 
+const CHUNK_SIZE: usize = 1024 * 1024; // 1MB chunk size
+const FLASH_SECTOR_SIZE: u32 = 0x1000;
+const ESP_IMAGE_MAGIC: u8 = 0xE9;
+
 #[derive(Clone, serde::Serialize)]
 struct Payload {
     pct: String,
 }
 
+#[derive(Clone, serde::Serialize)]
+struct RegionVerifyResult {
+    offset: u32,
+    size: usize,
+    expected_md5: String,
+    actual_md5: String,
+    passed: bool,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct FlashVerifyEvent {
+    passed: bool,
+    regions: Vec<RegionVerifyResult>,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct FlashEraseEvent {
+    offset: u32,
+    size: u32,
+}
+
 #[derive(Clone, serde::Serialize)]
 struct FlashProgressEvent {
     count: usize,
     total: usize,
 }
 
+// Summary of the chip connected to a port, read from the chip and its eFuses.
+#[derive(Clone, serde::Serialize)]
+pub struct ChipInfo {
+    chip: String,
+    revision: Option<String>,
+    crystal_frequency: u32,
+    flash_size: String,
+    mac_address: String,
+    features: Vec<String>,
+}
+
+// Reports progress in bytes across all segments written in one session,
+// so the frontend sees a single progress bar instead of one per segment.
 struct FlashProgress {
     window: Window,
-    current: usize,
+    completed: usize,
+    segment_size: usize,
+    chunks: usize,
     total: usize,
 }
 
-impl ProgressCallbacks for FlashProgress {
-    fn init(&mut self, addr: u32, total: usize) {
-        println!("init: addr: {:x}, total: {}", addr, total);
-        self.current = 0;
-        self.total = total;
+impl FlashProgress {
+    fn emit(&self, count: usize) {
         let flash_payload = FlashProgressEvent {
-            count: self.current,
-            total,
+            count,
+            total: self.total,
         };
         self.window.emit("flash-update", flash_payload).unwrap();
     }
+}
+
+impl ProgressCallbacks for FlashProgress {
+    fn init(&mut self, addr: u32, total: usize) {
+        debug!("Flashing segment at 0x{:x}, {} chunks", addr, total);
+        self.chunks = total;
+        self.emit(self.completed);
+    }
 
     fn update(&mut self, current: usize) {
-        println!("update: current: {}", current);
-        self.current = current;
-        let flash_payload = FlashProgressEvent {
-            count: current,
-            total: self.total,
-        };
-        self.window.emit("flash-update", flash_payload).unwrap();
+        debug!("Flashed chunk {} of {}", current, self.chunks);
+        let count = self.completed + self.segment_size * current / self.chunks.max(1);
+        self.emit(count);
     }
 
     fn finish(&mut self) {
-        println!("finish");
-        let flash_payload = FlashProgressEvent {
-            count: self.total,
-            total: self.total,
-        };
-        self.window.emit("flash-finish", flash_payload).unwrap();
+        debug!("Segment flashed");
+        self.completed += self.segment_size;
+        self.emit(self.completed);
+    }
+}
+
+// Flash mode, size and frequency which are written into the bootloader image header.
+// Fields set to None keep the value from the image.
+#[derive(Clone, Copy, Default)]
+pub struct FlashSettings {
+    pub mode: Option<FlashMode>,
+    pub size: Option<FlashSize>,
+    pub freq: Option<FlashFrequency>,
+}
+
+impl FlashSettings {
+    pub fn from_args(args: &FlashSettingsArgs) -> Result<Self, String> {
+        Ok(Self {
+            mode: args
+                .flash_mode
+                .as_deref()
+                .map(parse_flash_mode)
+                .transpose()?
+                .flatten(),
+            size: args
+                .flash_size
+                .as_deref()
+                .map(parse_flash_size)
+                .transpose()?
+                .flatten(),
+            freq: args
+                .flash_freq
+                .as_deref()
+                .map(parse_flash_freq)
+                .transpose()?
+                .flatten(),
+        })
+    }
+
+    // Values set in `overrides` take precedence.
+    pub fn with_overrides(self, overrides: FlashSettings) -> Self {
+        Self {
+            mode: overrides.mode.or(self.mode),
+            size: overrides.size.or(self.size),
+            freq: overrides.freq.or(self.freq),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.size.is_none() && self.freq.is_none()
+    }
+}
+
+// verify: compare the MD5 of every written region reported by the chip with the local data.
+// skip_unchanged: do not rewrite regions whose MD5 already matches.
+#[derive(Clone, Copy, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct VerifyOptions {
+    pub verify: bool,
+    pub skip_unchanged: bool,
+}
+
+// "keep" leaves the value from the image untouched, same as in esptool.
+pub fn parse_flash_mode(value: &str) -> Result<Option<FlashMode>, String> {
+    match value.to_lowercase().as_str() {
+        "keep" => Ok(None),
+        "qio" => Ok(Some(FlashMode::Qio)),
+        "qout" => Ok(Some(FlashMode::Qout)),
+        "dio" => Ok(Some(FlashMode::Dio)),
+        "dout" => Ok(Some(FlashMode::Dout)),
+        _ => Err(format!("Unsupported flash mode: {}", value)),
+    }
+}
+
+pub fn parse_flash_size(value: &str) -> Result<Option<FlashSize>, String> {
+    match value.to_uppercase().as_str() {
+        "KEEP" | "DETECT" => Ok(None),
+        "256KB" => Ok(Some(FlashSize::_256Kb)),
+        "512KB" => Ok(Some(FlashSize::_512Kb)),
+        "1MB" => Ok(Some(FlashSize::_1Mb)),
+        "2MB" => Ok(Some(FlashSize::_2Mb)),
+        "4MB" => Ok(Some(FlashSize::_4Mb)),
+        "8MB" => Ok(Some(FlashSize::_8Mb)),
+        "16MB" => Ok(Some(FlashSize::_16Mb)),
+        "32MB" => Ok(Some(FlashSize::_32Mb)),
+        "64MB" => Ok(Some(FlashSize::_64Mb)),
+        "128MB" => Ok(Some(FlashSize::_128Mb)),
+        "256MB" => Ok(Some(FlashSize::_256Mb)),
+        _ => Err(format!("Unsupported flash size: {}", value)),
+    }
+}
+
+pub fn parse_flash_freq(value: &str) -> Result<Option<FlashFrequency>, String> {
+    let lower = value.to_lowercase();
+    let mhz = lower.trim_end_matches("hz").trim_end_matches('m');
+    match mhz {
+        "keep" => Ok(None),
+        "12" => Ok(Some(FlashFrequency::_12Mhz)),
+        "15" => Ok(Some(FlashFrequency::_15Mhz)),
+        "16" => Ok(Some(FlashFrequency::_16Mhz)),
+        "20" => Ok(Some(FlashFrequency::_20Mhz)),
+        "24" => Ok(Some(FlashFrequency::_24Mhz)),
+        "26" => Ok(Some(FlashFrequency::_26Mhz)),
+        "30" => Ok(Some(FlashFrequency::_30Mhz)),
+        "40" => Ok(Some(FlashFrequency::_40Mhz)),
+        "48" => Ok(Some(FlashFrequency::_48Mhz)),
+        "60" => Ok(Some(FlashFrequency::_60Mhz)),
+        "80" => Ok(Some(FlashFrequency::_80Mhz)),
+        _ => Err(format!("Unsupported flash frequency: {}", value)),
     }
 }
 
@@ -68,53 +229,251 @@ pub fn get_serial_port_info(port_name: &str) -> io::Result<SerialPortInfo> {
     Err(io::Error::new(io::ErrorKind::NotFound, "Port not found"))
 }
 
-pub fn emit_error(window: &Window, error: &str) {
-    let error_payload = Payload {
-        pct: format!("Error: {}", error),
-    };
-    window.emit("error", error_payload).unwrap();
+fn encode_flash_mode(mode: FlashMode) -> u8 {
+    match mode {
+        FlashMode::Qio => 0,
+        FlashMode::Qout => 1,
+        FlashMode::Dio => 2,
+        _ => 3,
+    }
 }
 
-pub async fn flash_file(
-    window: Window,
-    _: AppHandle,
-    port: String,
-    file_path: String,
-    flash_offset: u32,
+fn bootloader_offset(chip: Chip) -> u32 {
+    match chip {
+        Chip::Esp32 | Chip::Esp32s2 => 0x1000,
+        _ => 0x0,
+    }
+}
+
+// Returns the position of the SHA256 digest appended after the image checksum.
+fn image_digest_offset(data: &[u8]) -> Option<usize> {
+    let segment_count = data[1] as usize;
+    // 8 bytes of common header followed by 16 bytes of extended header
+    let mut position = 24;
+    for _ in 0..segment_count {
+        let length = data.get(position + 4..position + 8)?;
+        let length = u32::from_le_bytes(length.try_into().ok()?) as usize;
+        position += 8 + length;
+    }
+    // The checksum byte is placed so that the image ends on a 16 byte boundary
+    position += 16 - position % 16;
+    if data.len() < position + 32 {
+        return None;
+    }
+    Some(position)
+}
+
+// Patch flash mode, size and frequency in the image header the same way esptool does,
+// recalculating the appended SHA256 digest if the image has one.
+fn update_image_header(
+    data: &mut [u8],
+    chip: Chip,
+    settings: &FlashSettings,
 ) -> Result<(), String> {
-    // let file_metadata = std::fs::metadata(&file_path);
-    // match file_metadata {
-    //     Ok(metadata) => {
-    //         if metadata.len() > 1500000 {
-    //             emit_error(&window, "File size cannot be greater than 1.5MB. Limitation: https://github.com/esp-rs/espflash/issues/453");
-    //             return Ok(());
-    //         }
-    //     }
-    //     Err(e) => {
-    //         emit_error(&window, &format!("Failed to get file metadata: {}", e));
-    //         return Ok(());
-    //     }
-    // }
+    if settings.is_empty() || data.len() < 24 || data[0] != ESP_IMAGE_MAGIC {
+        return Ok(());
+    }
 
-    let binary_file = PathBuf::from(file_path);
+    if let Some(mode) = settings.mode {
+        data[2] = encode_flash_mode(mode);
+    }
+    if let Some(size) = settings.size {
+        let size = size
+            .encode_flash_size(chip)
+            .map_err(|e| format!("Flash size error: {:?}", e))?;
+        data[3] = (data[3] & 0x0f) | (size << 4);
+    }
+    if let Some(freq) = settings.freq {
+        let freq = freq
+            .encode_flash_frequency(chip)
+            .map_err(|e| format!("Flash frequency error: {:?}", e))?;
+        data[3] = (data[3] & 0xf0) | (freq & 0x0f);
+    }
 
-    let mut data = read(&binary_file).unwrap();
+    let hash_appended = chip != Chip::Esp8266 && data[23] == 1;
+    if hash_appended {
+        if let Some(digest_offset) = image_digest_offset(data) {
+            let digest = Sha256::digest(&data[..digest_offset]);
+            data[digest_offset..digest_offset + 32].copy_from_slice(&digest);
+        }
+    }
 
+    Ok(())
+}
+
+// Partition table read from the device, `errors` lists validation problems.
+#[derive(Clone, serde::Serialize)]
+pub struct DevicePartitionTable {
+    pub partitions: Vec<PartitionEntry>,
+    flash_size: String,
+    errors: Vec<String>,
+}
+
+// Connected flasher together with what is needed to keep talking to the chip
+// once the Flasher has been turned back into an Interface.
+struct FlashSession {
+    flasher: Flasher,
+    port: String,
+    port_info: UsbPortInfo,
+    use_stub: bool,
+    after: ResetAfter,
+}
+
+// Operations which need the stub pass `require_stub`, otherwise the options decide.
+fn connect(
+    port: &str,
+    options: &FlashOptions,
+    require_stub: bool,
+) -> Result<FlashSession, DeviceError> {
     let dtr = Some(1);
     let rts = Some(0);
+    let use_stub = options.use_stub || require_stub;
 
-    // let port_info = get_serial_port_info(port.as_str()).unwrap();
-
-    println!("port: {}", port);
-    let serial_port_info = get_serial_port_info(port.as_str()).unwrap();
-    let port_info = match &serial_port_info.port_type {
-        serialport::SerialPortType::UsbPort(info) => Some(info.clone()),
-        _ => return Err("Port is not a USB port".to_string()),
+    let serial_port_info = get_serial_port_info(port).map_err(|e| DeviceError::from_io(port, e))?;
+    let mut port_info = match &serial_port_info.port_type {
+        serialport::SerialPortType::UsbPort(info) => info.clone(),
+        _ => return Err(DeviceError::NotUsbPort(port.to_string())),
     };
-    let serial = Interface::new(&serial_port_info, dtr, rts).unwrap();
+    port_info.pid = options.before.reset_pid(port_info.pid);
+    let serial = Interface::new(&serial_port_info, dtr, rts)
+        .map_err(|e| DeviceError::from_interface(port, e))?;
+
+    info!("Connecting to {}", port);
+    let flasher = Flasher::connect(serial, port_info.clone(), options.baud, use_stub)
+        .map_err(|e| DeviceError::from_espflash(port, e))?;
+    Ok(FlashSession {
+        flasher,
+        port: port.to_string(),
+        port_info,
+        use_stub,
+        after: options.after,
+    })
+}
+
+// Reset into the application unless the chip should stay in the bootloader.
+fn reset_device(
+    interface: &mut Interface,
+    port_info: &UsbPortInfo,
+    after: ResetAfter,
+) -> Result<(), serialport::Error> {
+    match after {
+        ResetAfter::HardReset => reset_after_flash(interface, port_info.pid),
+        ResetAfter::NoReset => Ok(()),
+    }
+}
+
+fn flash_error(window: &Window, port: &str, e: espflash::error::Error) -> DeviceError {
+    report_error(window, DeviceError::from_espflash(port, e))
+}
+
+fn loader_error(window: &Window, e: io::Error) -> DeviceError {
+    report_error(window, DeviceError::Flash(format!("Loader error: {}", e)))
+}
+
+fn to_hex(digest: [u8; 16]) -> String {
+    format!("{:x}", md5::Digest(digest))
+}
+
+fn is_region_unchanged(loader: &mut Loader, offset: u32, data: &[u8]) -> bool {
+    match loader.flash_md5(offset, data.len() as u32) {
+        Ok(digest) => digest == md5::compute(data).0,
+        Err(_) => false,
+    }
+}
+
+fn verify_regions(
+    loader: &mut Loader,
+    segments: &[(u32, Vec<u8>)],
+) -> io::Result<Vec<RegionVerifyResult>> {
+    let mut regions = Vec::new();
+    for (offset, data) in segments {
+        let expected = md5::compute(data).0;
+        let actual = loader.flash_md5(*offset, data.len() as u32)?;
+        regions.push(RegionVerifyResult {
+            offset: *offset,
+            size: data.len(),
+            expected_md5: to_hex(expected),
+            actual_md5: to_hex(actual),
+            passed: expected == actual,
+        });
+    }
+    Ok(regions)
+}
+
+fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(b"\x7fELF")
+}
 
-    println!("Connecting to port...");
-    let mut flasher = Flasher::connect(serial, port_info.unwrap(), None, false).unwrap();
+// Build the app image for the connected chip from an ELF file, together with
+// the default bootloader and partition table provided by espflash.
+fn elf_to_segments(
+    flasher: &mut Flasher,
+    elf_data: &[u8],
+) -> Result<Vec<(u32, Vec<u8>)>, espflash::error::Error> {
+    let device_info = flasher.device_info()?;
+    let elf_image = ElfFirmwareImage::try_from(elf_data)?;
+    let image = device_info.chip.into_target().get_flash_image(
+        &elf_image,
+        None,
+        None,
+        None,
+        device_info.revision,
+        None,
+        Some(device_info.flash_size),
+        None,
+    )?;
+
+    let segments = image
+        .flash_segments()
+        .map(|segment| (segment.addr, segment.data.into_owned()))
+        .collect();
+    Ok(segments)
+}
+
+// Write all segments within one connection, the target is reset only at the end.
+fn write_segments(
+    window: &Window,
+    session: FlashSession,
+    mut segments: Vec<(u32, Vec<u8>)>,
+    settings: FlashSettings,
+    verify_options: VerifyOptions,
+    cancel: &CancellationToken,
+) -> Result<(), DeviceError> {
+    let FlashSession {
+        flasher,
+        port,
+        port_info,
+        use_stub,
+        after,
+    } = session;
+    let chip = flasher.chip();
+
+    let boot_offset = bootloader_offset(chip);
+    for (offset, data) in segments.iter_mut() {
+        if *offset == boot_offset {
+            update_image_header(data, chip, &settings)
+                .map_err(|error| report_error(window, error))?;
+        }
+    }
+
+    let mut interface = flasher.into_interface();
+
+    let mut pending: Vec<&(u32, Vec<u8>)> = segments.iter().collect();
+    if verify_options.skip_unchanged {
+        let mut loader =
+            Loader::new(interface, chip, use_stub).map_err(|e| loader_error(window, e))?;
+        pending.retain(|(offset, data)| {
+            let unchanged = is_region_unchanged(&mut loader, *offset, data);
+            if unchanged {
+                let payload = Payload {
+                    pct: format!("Skipping unchanged region at 0x{:x}", offset),
+                };
+                window.emit("flash-event", payload).unwrap();
+            }
+            !unchanged
+        });
+        interface = loader.into_interface();
+    }
 
     // Emit the line to the frontend
     let payload = Payload {
@@ -122,37 +481,767 @@ pub async fn flash_file(
     };
     window.emit("flash-event", payload).unwrap();
 
+    let total = pending.iter().map(|(_, data)| data.len()).sum();
     let mut progress = FlashProgress {
         window: window.clone(),
-        total: 0,
-        current: 0,
+        completed: 0,
+        segment_size: 0,
+        chunks: 0,
+        total,
     };
 
-    let chunk_size = 1024 * 1024; // 1MB chunk size
-                                  // let total_size = data.len();
-    let mut offset = flash_offset;
-    // let mut current_offset = 0;
+    let mut connection = Connection::new(interface, port_info.clone());
+    if !pending.is_empty() {
+        let mut target = chip.flash_target(SpiAttachParams::default(), use_stub);
+        target
+            .begin(&mut connection)
+            .map_err(|e| flash_error(window, &port, e))?;
+
+        'segments: for (offset, data) in &pending {
+            let payload = Payload {
+                pct: format!("Writing {} bytes at 0x{:x}...", data.len(), offset),
+            };
+            window.emit("flash-event", payload).unwrap();
+
+            let mut chunk_offset = *offset;
+            for chunk in data.chunks(CHUNK_SIZE) {
+                // Writing can only be stopped between chunks
+                if cancel.is_cancelled() {
+                    break 'segments;
+                }
+                progress.segment_size = chunk.len();
+                let segment = RomSegment {
+                    addr: chunk_offset,
+                    data: Cow::Borrowed(chunk),
+                };
+                target
+                    .write_segment(&mut connection, segment, &mut Some(&mut progress))
+                    .map_err(|e| flash_error(window, &port, e))?;
+                chunk_offset += chunk.len() as u32;
+            }
+        }
+
+        if !cancel.is_cancelled() {
+            target
+                .finish(&mut connection, false)
+                .map_err(|e| flash_error(window, &port, e))?;
+        }
+    }
+    let mut interface = connection.into_interface();
+
+    if cancel.is_cancelled() {
+        let _ = reset_device(&mut interface, &port_info, after);
+        window.emit("flash-event", Some("Flash aborted")).unwrap();
+        return Ok(());
+    }
+
+    let flash_payload = FlashProgressEvent {
+        count: total,
+        total,
+    };
+    window.emit("flash-finish", flash_payload).unwrap();
 
-    while !data.is_empty() {
-        let (chunk, rest) = if data.len() > chunk_size {
-            data.split_at(chunk_size)
-        } else {
-            (data.as_ref(), &[][..])
+    let mut verify_result = Ok(());
+    if verify_options.verify {
+        let payload = Payload {
+            pct: "Verifying flash...".to_string(),
         };
+        window.emit("flash-event", payload).unwrap();
 
-        flasher
-            .write_bin_to_flash(offset, chunk, Some(&mut progress))
-            .map_err(|e| {
-                let error = format!("Flash error: {:?}", e);
-                emit_error(&window, &error);
-                error
-            })?;
+        let mut loader =
+            Loader::new(interface, chip, use_stub).map_err(|e| loader_error(window, e))?;
+        let regions =
+            verify_regions(&mut loader, &segments).map_err(|e| loader_error(window, e))?;
+        interface = loader.into_interface();
 
-        offset += chunk.len() as u32;
-        data = rest.to_vec();
+        let passed = regions.iter().all(|region| region.passed);
+        window
+            .emit("flash-verify", FlashVerifyEvent { passed, regions })
+            .unwrap();
+        if !passed {
+            verify_result = Err(report_error(window, DeviceError::VerifyFailed));
+        }
     }
 
+    reset_device(&mut interface, &port_info, after)
+        .map_err(|e| report_error(window, DeviceError::from_serial(&port, e)))?;
+
+    verify_result?;
     window.emit("flash-event", Some("Flash Done")).unwrap();
 
     Ok(())
 }
+
+pub async fn flash_file(
+    window: Window,
+    cancel: CancellationToken,
+    port: String,
+    file_path: String,
+    flash_offset: u32,
+    verify_options: VerifyOptions,
+    flash_options: FlashOptions,
+) -> Result<(), DeviceError> {
+    let settings = flash_options
+        .flash_settings()
+        .map_err(|error| report_error(&window, error))?;
+    let binary_file = PathBuf::from(file_path);
+
+    let data = read(&binary_file)
+        .map_err(|e| report_error(&window, DeviceError::file_read(&binary_file, e)))?;
+
+    // The ROM loader leaves download mode after writing, verification needs the stub
+    let mut session = connect(&port, &flash_options, verify_options.verify)
+        .map_err(|e| report_error(&window, e))?;
+
+    // ELF files carry their own load addresses, flash_offset applies only to raw binaries
+    let segments = if is_elf(&data) {
+        let payload = Payload {
+            pct: "Converting ELF file to app image...".to_string(),
+        };
+        window.emit("flash-event", payload).unwrap();
+        elf_to_segments(&mut session.flasher, &data).map_err(|e| flash_error(&window, &port, e))?
+    } else {
+        vec![(flash_offset, data)]
+    };
+
+    write_segments(
+        &window,
+        session,
+        segments,
+        settings,
+        verify_options,
+        &cancel,
+    )
+}
+
+// Flash all images listed in flasher_args.json of an ESP-IDF build directory.
+pub async fn flash_build(
+    window: Window,
+    cancel: CancellationToken,
+    port: String,
+    build_path: String,
+    verify_options: VerifyOptions,
+    flash_options: FlashOptions,
+) -> Result<(), DeviceError> {
+    let flasher_args =
+        load_flasher_args(&build_path).map_err(|error| report_error(&window, error))?;
+    let settings = FlashSettings::from_args(&flasher_args.settings)
+        .and_then(|settings| Ok(settings.with_overrides(flash_options.flash_settings()?)))
+        .map_err(|error| report_error(&window, error))?;
+
+    let mut segments = Vec::new();
+    for FlashImage { offset, file_path } in flasher_args.images {
+        let data = read(&file_path)
+            .map_err(|e| report_error(&window, DeviceError::file_read(&file_path, e)))?;
+        segments.push((offset, data));
+    }
+
+    let session = connect(&port, &flash_options, verify_options.verify)
+        .map_err(|e| report_error(&window, e))?;
+    write_segments(
+        &window,
+        session,
+        segments,
+        settings,
+        verify_options,
+        &cancel,
+    )
+}
+
+// Stream `length` bytes of flash starting at `offset` into `output`.
+// Returns false when the read was cancelled.
+fn read_region(
+    window: &Window,
+    cancel: &CancellationToken,
+    port: &str,
+    offset: u32,
+    length: u32,
+    flash_options: &FlashOptions,
+    output: &mut dyn Write,
+) -> Result<bool, DeviceError> {
+    // Reading flash is supported only by the stub
+    let FlashSession {
+        mut flasher,
+        port_info,
+        use_stub,
+        after,
+        ..
+    } = connect(port, flash_options, true).map_err(|e| report_error(window, e))?;
+    let chip = flasher.chip();
+
+    let flash_size = flasher
+        .device_info()
+        .map_err(|e| flash_error(window, port, e))?
+        .flash_size
+        .size();
+    if length == 0 || offset as u64 + length as u64 > flash_size as u64 {
+        let error = format!(
+            "Region 0x{:x}..0x{:x} is outside of the flash (0x{:x} bytes)",
+            offset,
+            offset as u64 + length as u64,
+            flash_size
+        );
+        return Err(report_error(window, error));
+    }
+
+    let payload = Payload {
+        pct: format!("Reading 0x{:x} bytes from 0x{:x}...", length, offset),
+    };
+    window.emit("flash-event", payload).unwrap();
+
+    let mut loader = Loader::new(flasher.into_interface(), chip, use_stub)
+        .map_err(|e| loader_error(window, e))?;
+    let mut count = 0;
+    let result = loader.read_flash(offset, length, |block| {
+        output.write_all(block)?;
+        count += block.len();
+        let flash_payload = FlashProgressEvent {
+            count,
+            total: length as usize,
+        };
+        window.emit("flash-read-update", flash_payload).unwrap();
+        Ok(!cancel.is_cancelled())
+    });
+
+    // Reset also stops the stub from sending the rest of the data after an abort
+    let mut interface = loader.into_interface();
+    let _ = reset_device(&mut interface, &port_info, after);
+
+    match result {
+        Ok(true) => {
+            let flash_payload = FlashProgressEvent {
+                count,
+                total: length as usize,
+            };
+            window.emit("flash-read-finish", flash_payload).unwrap();
+            window.emit("flash-event", Some("Read Done")).unwrap();
+            Ok(true)
+        }
+        Ok(false) => {
+            window.emit("flash-event", Some("Read aborted")).unwrap();
+            Ok(false)
+        }
+        Err(e) => Err(loader_error(window, e)),
+    }
+}
+
+// Read `length` bytes of flash starting at `offset` into the output file.
+pub async fn read_flash(
+    window: Window,
+    cancel: CancellationToken,
+    port: String,
+    offset: u32,
+    length: u32,
+    output_path: String,
+    flash_options: FlashOptions,
+) -> Result<(), DeviceError> {
+    let mut file = File::create(&output_path)
+        .map_err(|e| report_error(&window, DeviceError::file_write(&output_path, e)))?;
+    let result = read_region(
+        &window,
+        &cancel,
+        &port,
+        offset,
+        length,
+        &flash_options,
+        &mut file,
+    );
+    drop(file);
+
+    match result {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            let _ = std::fs::remove_file(&output_path);
+            Ok(())
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&output_path);
+            Err(e)
+        }
+    }
+}
+
+// Read a region of flash into memory, None when the read was cancelled.
+pub fn read_flash_data(
+    window: &Window,
+    cancel: &CancellationToken,
+    port: &str,
+    offset: u32,
+    length: u32,
+    flash_options: &FlashOptions,
+) -> Result<Option<Vec<u8>>, DeviceError> {
+    let mut data = Vec::with_capacity(length as usize);
+    let completed = read_region(
+        window,
+        cancel,
+        port,
+        offset,
+        length,
+        flash_options,
+        &mut data,
+    )?;
+    Ok(completed.then_some(data))
+}
+
+fn format_flash_size(bytes: u32) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{}MB", bytes / (1024 * 1024))
+    } else {
+        format!("{}KB", bytes / 1024)
+    }
+}
+
+pub async fn get_chip_info(
+    port: String,
+    flash_options: FlashOptions,
+) -> Result<ChipInfo, DeviceError> {
+    let FlashSession {
+        mut flasher,
+        port_info,
+        after,
+        ..
+    } = connect(&port, &flash_options, false)?;
+
+    let device_info = flasher
+        .device_info()
+        .map_err(|e| DeviceError::from_espflash(&port, e))?;
+
+    // Let the application run again
+    let mut interface = flasher.into_interface();
+    let _ = reset_device(&mut interface, &port_info, after);
+
+    Ok(ChipInfo {
+        chip: device_info.chip.to_string(),
+        revision: device_info
+            .revision
+            .map(|(major, minor)| format!("v{}.{}", major, minor)),
+        crystal_frequency: device_info.crystal_frequency,
+        flash_size: format_flash_size(device_info.flash_size.size()),
+        mac_address: device_info.mac_address,
+        features: device_info.features,
+    })
+}
+
+// Erase the whole flash when `region` is None, otherwise only the given offset and size.
+fn erase(
+    window: &Window,
+    port: &str,
+    region: Option<(u32, u32)>,
+    flash_options: &FlashOptions,
+) -> Result<(), DeviceError> {
+    // Erase commands are supported only by the stub
+    let FlashSession {
+        mut flasher,
+        port_info,
+        after,
+        ..
+    } = connect(port, flash_options, true).map_err(|e| report_error(window, e))?;
+
+    let (offset, size) = match region {
+        Some(region) => region,
+        None => (
+            0,
+            flasher
+                .device_info()
+                .map_err(|e| flash_error(window, port, e))?
+                .flash_size
+                .size(),
+        ),
+    };
+    let payload = Payload {
+        pct: format!("Erasing 0x{:x} bytes at 0x{:x}...", size, offset),
+    };
+    window.emit("flash-event", payload).unwrap();
+
+    let result = match region {
+        Some(_) => flasher.erase_region(offset, size),
+        None => flasher.erase_flash(),
+    };
+    result.map_err(|e| flash_error(window, port, e))?;
+
+    let mut interface = flasher.into_interface();
+    let _ = reset_device(&mut interface, &port_info, after);
+
+    window
+        .emit("flash-erase-finish", FlashEraseEvent { offset, size })
+        .unwrap();
+    window.emit("flash-event", Some("Erase Done")).unwrap();
+    Ok(())
+}
+
+pub async fn erase_flash(
+    window: Window,
+    _: CancellationToken,
+    port: String,
+    flash_options: FlashOptions,
+) -> Result<(), DeviceError> {
+    erase(&window, &port, None, &flash_options)
+}
+
+pub async fn erase_region(
+    window: Window,
+    _: CancellationToken,
+    port: String,
+    offset: u32,
+    size: u32,
+    flash_options: FlashOptions,
+) -> Result<(), DeviceError> {
+    if size == 0 || offset % FLASH_SECTOR_SIZE != 0 || size % FLASH_SECTOR_SIZE != 0 {
+        let error = format!(
+            "Offset and size must be non-zero multiples of the sector size (0x{:x})",
+            FLASH_SECTOR_SIZE
+        );
+        return Err(report_error(&window, error));
+    }
+    erase(&window, &port, Some((offset, size)), &flash_options)
+}
+
+pub async fn read_partition_table(
+    port: String,
+    flash_options: FlashOptions,
+) -> Result<DevicePartitionTable, DeviceError> {
+    // Reading flash is supported only by the stub
+    let FlashSession {
+        mut flasher,
+        port_info,
+        use_stub,
+        after,
+        ..
+    } = connect(&port, &flash_options, true)?;
+    let chip = flasher.chip();
+
+    let flash_size = flasher
+        .device_info()
+        .map_err(|e| DeviceError::from_espflash(&port, e))?
+        .flash_size
+        .size();
+
+    let mut loader = Loader::new(flasher.into_interface(), chip, use_stub)
+        .map_err(|e| DeviceError::Flash(format!("Loader error: {}", e)))?;
+    let mut data = Vec::with_capacity(PARTITION_TABLE_SIZE as usize);
+    let result = loader.read_flash(PARTITION_TABLE_OFFSET, PARTITION_TABLE_SIZE, |block| {
+        data.extend_from_slice(block);
+        Ok(true)
+    });
+
+    let mut interface = loader.into_interface();
+    let _ = reset_device(&mut interface, &port_info, after);
+
+    result.map_err(|e| DeviceError::Flash(format!("Failed to read partition table: {}", e)))?;
+    let partitions = parse_partition_table(data)?;
+    let errors = validate(&partitions, Some(flash_size));
+    Ok(DevicePartitionTable {
+        partitions,
+        flash_size: format_flash_size(flash_size),
+        errors,
+    })
+}
+
+// Look up a partition by name in the table currently stored on the device.
+async fn resolve_partition(
+    window: &Window,
+    port: &str,
+    name: &str,
+    flash_options: &FlashOptions,
+) -> Result<PartitionEntry, DeviceError> {
+    let table = read_partition_table(port.to_string(), flash_options.clone())
+        .await
+        .map_err(|error| report_error(window, error))?;
+    find_partition(&table.partitions, name)
+        .cloned()
+        .map_err(|error| report_error(window, error))
+}
+
+pub async fn flash_partition(
+    window: Window,
+    cancel: CancellationToken,
+    port: String,
+    name: String,
+    file_path: String,
+    verify_options: VerifyOptions,
+    flash_options: FlashOptions,
+) -> Result<(), DeviceError> {
+    let settings = flash_options
+        .flash_settings()
+        .map_err(|error| report_error(&window, error))?;
+    let partition = resolve_partition(&window, &port, &name, &flash_options).await?;
+
+    let data = read(&file_path)
+        .map_err(|e| report_error(&window, DeviceError::file_read(&file_path, e)))?;
+    if data.len() as u64 > partition.size as u64 {
+        let error = format!(
+            "File is larger (0x{:x} bytes) than partition '{}' (0x{:x} bytes)",
+            data.len(),
+            name,
+            partition.size
+        );
+        return Err(report_error(&window, error));
+    }
+
+    let session = connect(&port, &flash_options, verify_options.verify)
+        .map_err(|e| report_error(&window, e))?;
+    write_segments(
+        &window,
+        session,
+        vec![(partition.offset, data)],
+        settings,
+        verify_options,
+        &cancel,
+    )
+}
+
+pub async fn erase_partition(
+    window: Window,
+    _: CancellationToken,
+    port: String,
+    name: String,
+    flash_options: FlashOptions,
+) -> Result<(), DeviceError> {
+    let partition = resolve_partition(&window, &port, &name, &flash_options).await?;
+    erase(
+        &window,
+        &port,
+        Some((partition.offset, partition.size)),
+        &flash_options,
+    )
+}
+
+pub async fn read_partition(
+    window: Window,
+    cancel: CancellationToken,
+    port: String,
+    name: String,
+    output_path: String,
+    flash_options: FlashOptions,
+) -> Result<(), DeviceError> {
+    let partition = resolve_partition(&window, &port, &name, &flash_options).await?;
+    read_flash(
+        window,
+        cancel,
+        port,
+        partition.offset,
+        partition.size,
+        output_path,
+        flash_options,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIO: u8 = 2;
+    // 4MB and 40MHz on the ESP32
+    const SIZE_FREQ: u8 = 0x20;
+
+    // Bootloader image with segments of the given lengths laid out like
+    // esptool does: padding up to the checksum byte ending the image on a
+    // 16 byte boundary, followed by the SHA-256 digest when `hash` is set.
+    fn image(segments: &[usize], hash: bool) -> Vec<u8> {
+        let mut data = vec![ESP_IMAGE_MAGIC, segments.len() as u8, DIO, SIZE_FREQ];
+        data.extend_from_slice(&0x4008_0400u32.to_le_bytes());
+        let mut extended_header = [0; 16];
+        extended_header[0] = 0xEE;
+        extended_header[15] = hash as u8;
+        data.extend_from_slice(&extended_header);
+        for (index, length) in segments.iter().enumerate() {
+            data.extend_from_slice(&(0x3FF8_0000 + index as u32 * 0x1000).to_le_bytes());
+            data.extend_from_slice(&(*length as u32).to_le_bytes());
+            data.extend((0..*length).map(|i| (i % 251) as u8));
+        }
+        while data.len() % 16 != 15 {
+            data.push(0);
+        }
+        data.push(0xEF);
+        if hash {
+            let digest = Sha256::digest(&data);
+            data.extend_from_slice(&digest);
+        }
+        data
+    }
+
+    fn settings(mode: &str, size: &str, freq: &str) -> FlashSettings {
+        FlashSettings {
+            mode: parse_flash_mode(mode).unwrap(),
+            size: parse_flash_size(size).unwrap(),
+            freq: parse_flash_freq(freq).unwrap(),
+        }
+    }
+
+    #[test]
+    fn parse_flash_modes() {
+        let cases = [
+            ("keep", Some(None)),
+            ("qio", Some(Some(0))),
+            ("QOUT", Some(Some(1))),
+            ("dio", Some(Some(2))),
+            ("Dout", Some(Some(3))),
+            ("fastest", None),
+            ("", None),
+        ];
+        for (value, expected) in cases {
+            let mode = parse_flash_mode(value).ok();
+            assert_eq!(
+                mode.map(|mode| mode.map(encode_flash_mode)),
+                expected,
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn parse_flash_sizes() {
+        let cases = [
+            ("keep", Some("None")),
+            ("detect", Some("None")),
+            ("256KB", Some("Some(_256Kb)")),
+            ("4MB", Some("Some(_4Mb)")),
+            ("16mb", Some("Some(_16Mb)")),
+            ("256MB", Some("Some(_256Mb)")),
+            ("4M", None),
+            ("3MB", None),
+        ];
+        for (value, expected) in cases {
+            let size = parse_flash_size(value)
+                .ok()
+                .map(|size| format!("{:?}", size));
+            assert_eq!(size.as_deref(), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn parse_flash_frequencies() {
+        let cases = [
+            ("keep", Some("None")),
+            ("40m", Some("Some(_40Mhz)")),
+            ("80M", Some("Some(_80Mhz)")),
+            ("26mhz", Some("Some(_26Mhz)")),
+            ("20", Some("Some(_20Mhz)")),
+            ("48MHz", Some("Some(_48Mhz)")),
+            ("100m", None),
+            ("fast", None),
+        ];
+        for (value, expected) in cases {
+            let freq = parse_flash_freq(value)
+                .ok()
+                .map(|freq| format!("{:?}", freq));
+            assert_eq!(freq.as_deref(), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn digest_offsets() {
+        let cases: [&[usize]; 8] = [
+            &[],
+            &[4],
+            &[7],
+            &[8],
+            &[15],
+            &[16],
+            &[0x1234, 3],
+            &[1, 2, 3, 4, 5],
+        ];
+        for segments in cases {
+            let data = image(segments, true);
+            assert_eq!(
+                image_digest_offset(&data),
+                Some(data.len() - 32),
+                "{:?}",
+                segments
+            );
+            assert_eq!((data.len() - 32) % 16, 0);
+
+            // No room for a digest
+            let data = image(segments, false);
+            assert_eq!(image_digest_offset(&data), None, "{:?}", segments);
+        }
+    }
+
+    #[test]
+    fn digest_offset_of_truncated_image() {
+        let data = image(&[0x100, 0x20], true);
+        for length in [24, 30, 40, 0x100, data.len() - 1] {
+            assert_eq!(image_digest_offset(&data[..length]), None, "{}", length);
+        }
+        // A segment length beyond the end of the image
+        let mut data = image(&[0x10], true);
+        data[28..32].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(image_digest_offset(&data), None);
+    }
+
+    #[test]
+    fn update_header_bytes() {
+        // mode, size, freq, byte 2, byte 3
+        let cases = [
+            ("qio", "16MB", "80m", 0x00, 0x4F),
+            ("dout", "keep", "keep", 0x03, SIZE_FREQ),
+            ("keep", "8MB", "keep", DIO, 0x30),
+            ("keep", "keep", "26m", DIO, 0x21),
+            ("keep", "1MB", "20m", DIO, 0x02),
+            ("qout", "2MB", "40m", 0x01, 0x10),
+        ];
+        for (mode, size, freq, byte2, byte3) in cases {
+            let mut data = image(&[0x40, 0x13], true);
+            let original = data.clone();
+            update_image_header(&mut data, Chip::Esp32, &settings(mode, size, freq)).unwrap();
+
+            assert_eq!(
+                (data[2], data[3]),
+                (byte2, byte3),
+                "{} {} {}",
+                mode,
+                size,
+                freq
+            );
+            let digest_offset = data.len() - 32;
+            // Only the header and the digest change
+            assert_eq!(data[4..digest_offset], original[4..digest_offset]);
+            assert_eq!(
+                data[digest_offset..],
+                Sha256::digest(&data[..digest_offset])[..]
+            );
+        }
+    }
+
+    #[test]
+    fn update_header_without_hash() {
+        let mut data = image(&[0x40, 0x13], false);
+        let original = data.clone();
+        update_image_header(&mut data, Chip::Esp32, &settings("qio", "4MB", "80m")).unwrap();
+
+        assert_eq!((data[2], data[3]), (0x00, 0x2F));
+        assert_eq!(data[4..], original[4..]);
+    }
+
+    #[test]
+    fn images_left_untouched() {
+        let cases = [
+            // Nothing to change
+            (image(&[0x40], true), settings("keep", "keep", "keep")),
+            // No image
+            (vec![0xFF; 0x100], settings("qio", "4MB", "80m")),
+            (
+                image(&[0x40], true)[..20].to_vec(),
+                settings("qio", "4MB", "80m"),
+            ),
+        ];
+        for (data, settings) in cases {
+            let mut updated = data.clone();
+            update_image_header(&mut updated, Chip::Esp32, &settings).unwrap();
+            assert_eq!(updated, data);
+        }
+    }
+
+    #[test]
+    fn update_header_of_esp8266_keeps_data() {
+        // The ESP8266 image has no extended header, byte 23 is segment data
+        let mut data = image(&[0x40], true);
+        let original = data.clone();
+        update_image_header(&mut data, Chip::Esp8266, &settings("dout", "keep", "keep")).unwrap();
+
+        assert_eq!(data[2], 0x03);
+        assert_eq!(data[3..], original[3..]);
+    }
+}
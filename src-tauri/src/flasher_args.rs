@@ -0,0 +1,221 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// Name of the file generated by ESP-IDF in the project build directory.
+pub const FLASHER_ARGS_FILE: &str = "flasher_args.json";
+
+#[derive(Deserialize)]
+struct FlasherArgsFile {
+    #[serde(default)]
+    flash_settings: FlashSettingsArgs,
+    flash_files: BTreeMap<String, String>,
+}
+
+// Flash settings as written by ESP-IDF, e.g. "dio", "2MB", "40m".
+#[derive(Clone, Default, Deserialize, serde::Serialize)]
+pub struct FlashSettingsArgs {
+    pub flash_mode: Option<String>,
+    pub flash_size: Option<String>,
+    pub flash_freq: Option<String>,
+}
+
+// One binary which should be written at the given flash offset.
+#[derive(Clone, serde::Serialize)]
+pub struct FlashImage {
+    pub offset: u32,
+    pub file_path: PathBuf,
+}
+
+pub struct FlasherArgs {
+    pub settings: FlashSettingsArgs,
+    pub images: Vec<FlashImage>,
+}
+
+pub fn parse_offset(value: &str) -> Result<u32, String> {
+    let value = value.trim();
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse::<u32>(),
+    };
+    parsed.map_err(|_| format!("Invalid flash offset: {}", value))
+}
+
+// Accepts either the ESP-IDF build directory or the path to flasher_args.json itself.
+pub fn load_flasher_args(project_path: &str) -> Result<FlasherArgs, String> {
+    let path = Path::new(project_path);
+    let args_path = if path.is_dir() {
+        path.join(FLASHER_ARGS_FILE)
+    } else {
+        path.to_path_buf()
+    };
+    let build_dir = args_path
+        .parent()
+        .ok_or("Unable to determine build directory")?
+        .to_path_buf();
+
+    let content = std::fs::read_to_string(&args_path)
+        .map_err(|err| format!("Failed to read {}: {}", args_path.display(), err))?;
+    let args: FlasherArgsFile = serde_json::from_str(&content)
+        .map_err(|err| format!("Failed to parse {}: {}", args_path.display(), err))?;
+
+    let mut images = Vec::new();
+    for (offset, file) in args.flash_files {
+        let file_path = build_dir.join(file);
+        if !file_path.is_file() {
+            return Err(format!("Missing flash file: {}", file_path.display()));
+        }
+        images.push(FlashImage {
+            offset: parse_offset(&offset)?,
+            file_path,
+        });
+    }
+    images.sort_by_key(|image| image.offset);
+
+    if images.is_empty() {
+        return Err(format!("No flash files listed in {}", args_path.display()));
+    }
+
+    Ok(FlasherArgs {
+        settings: args.flash_settings,
+        images,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Directory with flasher_args.json and the listed files, removed when dropped.
+    struct BuildDir(PathBuf);
+
+    impl BuildDir {
+        fn new(name: &str, flasher_args: &str, files: &[&str]) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("esp-workbench-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(FLASHER_ARGS_FILE), flasher_args).unwrap();
+            for file in files {
+                let path = dir.join(file);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, [0xE9]).unwrap();
+            }
+            BuildDir(dir)
+        }
+    }
+
+    impl Drop for BuildDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn parse_offset_accepts_hex_and_decimal() {
+        let cases = [
+            ("0x1000", Some(0x1000)),
+            ("0X8000", Some(0x8000)),
+            ("0x0", Some(0)),
+            ("0xffffffff", Some(u32::MAX)),
+            ("65536", Some(65536)),
+            (" 0x10000 ", Some(0x10000)),
+            ("0", Some(0)),
+            ("0x100000000", None),
+            ("4294967296", None),
+            ("0x", None),
+            ("0xg000", None),
+            ("1000h", None),
+            ("-1", None),
+            ("", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_offset(value).ok(), expected, "offset {:?}", value);
+        }
+    }
+
+    #[test]
+    fn load_flasher_args_sorts_images_by_offset() {
+        let build_dir = BuildDir::new(
+            "flasher-args-sorted",
+            r#"{
+                "flash_settings": { "flash_mode": "dio", "flash_size": "2MB", "flash_freq": "40m" },
+                "flash_files": {
+                    "0x10000": "app.bin",
+                    "0x1000": "bootloader/bootloader.bin",
+                    "0x8000": "partition_table/partition-table.bin"
+                }
+            }"#,
+            &[
+                "app.bin",
+                "bootloader/bootloader.bin",
+                "partition_table/partition-table.bin",
+            ],
+        );
+
+        // Both the directory and the file itself are accepted
+        let args_file = build_dir.0.join(FLASHER_ARGS_FILE);
+        for path in [&build_dir.0, &args_file] {
+            let args = load_flasher_args(path.to_str().unwrap()).unwrap();
+            let offsets: Vec<u32> = args.images.iter().map(|image| image.offset).collect();
+            assert_eq!(offsets, [0x1000, 0x8000, 0x10000]);
+            assert_eq!(
+                args.images[0].file_path,
+                build_dir.0.join("bootloader/bootloader.bin")
+            );
+            assert_eq!(args.settings.flash_mode.as_deref(), Some("dio"));
+            assert_eq!(args.settings.flash_size.as_deref(), Some("2MB"));
+        }
+    }
+
+    #[test]
+    fn load_flasher_args_without_flash_settings() {
+        let build_dir = BuildDir::new(
+            "flasher-args-no-settings",
+            r#"{ "flash_files": { "65536": "app.bin" } }"#,
+            &["app.bin"],
+        );
+        let args = load_flasher_args(build_dir.0.to_str().unwrap()).unwrap();
+        assert_eq!(args.images.len(), 1);
+        assert_eq!(args.images[0].offset, 0x10000);
+        assert!(args.settings.flash_mode.is_none());
+    }
+
+    #[test]
+    fn load_flasher_args_rejects_invalid_files() {
+        let cases = [
+            // File listed but not built
+            (
+                r#"{ "flash_files": { "0x10000": "missing.bin" } }"#,
+                "Missing flash file",
+            ),
+            (
+                r#"{ "flash_files": { "0xzz": "app.bin" } }"#,
+                "Invalid flash offset",
+            ),
+            (r#"{ "flash_files": {} }"#, "No flash files"),
+            (r#"{ "flash_settings": {} }"#, "Failed to parse"),
+        ];
+        for (index, (flasher_args, expected)) in cases.iter().enumerate() {
+            let build_dir = BuildDir::new(
+                &format!("flasher-args-invalid-{}", index),
+                flasher_args,
+                &["app.bin"],
+            );
+            let err = load_flasher_args(build_dir.0.to_str().unwrap())
+                .err()
+                .unwrap();
+            assert!(err.contains(expected), "{}: {}", flasher_args, err);
+        }
+    }
+
+    #[test]
+    fn load_flasher_args_missing_file() {
+        let path = std::env::temp_dir().join("esp-workbench-no-such-build-dir/flasher_args.json");
+        let err = load_flasher_args(path.to_str().unwrap()).err().unwrap();
+        assert!(err.starts_with("Failed to read"), "{}", err);
+    }
+}
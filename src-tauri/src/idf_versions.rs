@@ -0,0 +1,454 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::AppHandle;
+
+use crate::network::load_network_settings;
+
+const IDF_VERSIONS_PATH: &str = "dl/esp-idf/idf_versions.js";
+const CACHE_FILE: &str = "idf_versions.json";
+// The list changes with ESP-IDF releases, once a day is enough
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Target chip as listed by the version selector of the ESP-IDF documentation.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct IdfTarget {
+    pub text: String,
+    pub value: String,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct IdfVersion {
+    pub name: String,
+    // Start date of the release branch
+    pub release_date: Option<String>,
+    pub end_of_life: bool,
+    pub supported_targets: Vec<String>,
+    pub pre_release: bool,
+    // Still maintained, but superseded by a newer bugfix release
+    pub old: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct IdfVersions {
+    pub versions: Vec<IdfVersion>,
+    pub targets: Vec<IdfTarget>,
+    // Seconds since the Unix epoch when the list was downloaded
+    pub fetched_at: u64,
+    // Downloading failed, this is the last known list
+    #[serde(skip_deserializing)]
+    pub offline: bool,
+}
+
+// idf_versions.js defines DOCUMENTATION_VERSIONS with these fields.
+#[derive(Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+struct DocumentationVersions {
+    #[serde(default)]
+    defaults: RawVersion,
+    versions: Vec<RawVersion>,
+    #[serde(default)]
+    idf_targets: Vec<IdfTarget>,
+    #[serde(default)]
+    releases: HashMap<String, RawRelease>,
+}
+
+// Fields missing in a version are taken from DEFAULTS.
+#[derive(Default, Deserialize)]
+struct RawVersion {
+    #[serde(default)]
+    name: String,
+    old: Option<bool>,
+    end_of_life: Option<bool>,
+    pre_release: Option<bool>,
+    has_targets: Option<bool>,
+    supported_targets: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct RawRelease {
+    start_date: Option<String>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+// Turn a JavaScript object literal into JSON: keys get quotes, single quoted
+// strings become double quoted, comments and trailing commas are dropped.
+fn js_object_to_json(source: &str) -> String {
+    let chars: Vec<char> = source.chars().collect();
+    // First character after `from` that is neither whitespace nor in a comment
+    let next_token = |from: usize| {
+        let mut i = from;
+        while i < chars.len() {
+            match (chars[i], chars.get(i + 1)) {
+                (c, _) if c.is_whitespace() => i += 1,
+                ('/', Some('/')) => {
+                    while i < chars.len() && chars[i] != '\n' {
+                        i += 1;
+                    }
+                }
+                ('/', Some('*')) => {
+                    i += 2;
+                    while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                        i += 1;
+                    }
+                    i += 2;
+                }
+                (c, _) => return Some(c),
+            }
+        }
+        None
+    };
+    let mut json = String::with_capacity(source.len());
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            quote @ ('"' | '\'') => {
+                json.push('"');
+                i += 1;
+                while i < chars.len() && chars[i] != quote {
+                    match chars[i] {
+                        // \' is no valid escape in JSON
+                        '\\' if chars.get(i + 1) == Some(&'\'') => {
+                            json.push('\'');
+                            i += 1;
+                        }
+                        '\\' if i + 1 < chars.len() => {
+                            json.push('\\');
+                            json.push(chars[i + 1]);
+                            i += 1;
+                        }
+                        '"' => json.push_str("\\\""),
+                        c => json.push(c),
+                    }
+                    i += 1;
+                }
+                json.push('"');
+                i += 1;
+            }
+            '/' if chars.get(i + 1) == Some(&'/') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    i += 1;
+                }
+                i += 2;
+            }
+            ',' => {
+                if !matches!(next_token(i + 1), Some('}' | ']')) {
+                    json.push(',');
+                }
+                i += 1;
+            }
+            c if c.is_ascii_alphabetic() || c == '_' || c == '$' => {
+                let start = i;
+                while i < chars.len()
+                    && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+                {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                // Only keys are followed by a colon, true, false and null stay as they are
+                if next_token(i) == Some(':') {
+                    json.push_str(&format!("\"{}\"", word));
+                } else {
+                    json.push_str(&word);
+                }
+            }
+            c => {
+                json.push(c);
+                i += 1;
+            }
+        }
+    }
+    json
+}
+
+// Release branches are listed as "v5.1" while versions are e.g. "v5.1.2".
+fn release_date(releases: &HashMap<String, RawRelease>, name: &str) -> Option<String> {
+    let branch: String = name.splitn(3, '.').take(2).collect::<Vec<_>>().join(".");
+    releases
+        .get(name)
+        .or_else(|| releases.get(&branch))
+        .and_then(|release| release.start_date.clone())
+}
+
+fn parse_idf_versions(source: &str) -> Result<IdfVersions, String> {
+    // Only the object assigned to DOCUMENTATION_VERSIONS is of interest
+    let object_start = source.find('{').ok_or("Object start not found")?;
+    let object_end = source.rfind('}').ok_or("Object end not found")? + 1;
+    let json = js_object_to_json(&source[object_start..object_end]);
+    let raw: DocumentationVersions =
+        serde_json::from_str(&json).map_err(|e| format!("Invalid ESP-IDF version list: {}", e))?;
+
+    let defaults = &raw.defaults;
+    let versions = raw
+        .versions
+        .iter()
+        .map(|version| {
+            let has_targets = version.has_targets.or(defaults.has_targets) == Some(true);
+            let supported_targets = match has_targets {
+                true => version.supported_targets.as_ref(),
+                false => None,
+            }
+            .or(defaults.supported_targets.as_ref())
+            .cloned()
+            .unwrap_or_default();
+            IdfVersion {
+                name: version.name.clone(),
+                release_date: release_date(&raw.releases, &version.name),
+                end_of_life: version.end_of_life.or(defaults.end_of_life) == Some(true),
+                supported_targets,
+                pre_release: version.pre_release.or(defaults.pre_release) == Some(true),
+                old: version.old.or(defaults.old) == Some(true),
+            }
+        })
+        .collect();
+
+    Ok(IdfVersions {
+        versions,
+        targets: raw.idf_targets,
+        fetched_at: now(),
+        offline: false,
+    })
+}
+
+fn cache_path(app: &AppHandle) -> Option<PathBuf> {
+    app.path_resolver()
+        .app_cache_dir()
+        .map(|dir| dir.join(CACHE_FILE))
+}
+
+fn load_cache(app: &AppHandle) -> Option<IdfVersions> {
+    let content = std::fs::read_to_string(cache_path(app)?).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn save_cache(app: &AppHandle, versions: &IdfVersions) -> Result<(), String> {
+    let path = cache_path(app).ok_or("Unable to determine the application cache directory")?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| format!("Failed to create {}: {}", parent.display(), err))?;
+    }
+    let content = serde_json::to_string(versions).map_err(|err| err.to_string())?;
+    std::fs::write(&path, content)
+        .map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+}
+
+async fn fetch_idf_versions(app: &AppHandle) -> Result<IdfVersions, String> {
+    let settings = load_network_settings(app);
+    let url = settings.espressif_url(IDF_VERSIONS_PATH);
+    let client = settings.client()?;
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| format!("Failed to make request: {}", err))?;
+    let js_versions_file = response
+        .text()
+        .await
+        .map_err(|err| format!("Failed to read response: {}", err))?;
+    parse_idf_versions(&js_versions_file)
+}
+
+// Command to get the ESP-IDF releases. The list is cached for a day, `refresh`
+// downloads it anyway. Without network the last known list is returned.
+#[tauri::command]
+pub async fn get_available_idf_versions(
+    app: AppHandle,
+    refresh: Option<bool>,
+) -> Result<IdfVersions, String> {
+    let cached = load_cache(&app);
+    if let Some(cached) = &cached {
+        let age = now().saturating_sub(cached.fetched_at);
+        if !refresh.unwrap_or(false) && age < CACHE_TTL.as_secs() {
+            return Ok(cached.clone());
+        }
+    }
+
+    match fetch_idf_versions(&app).await {
+        Ok(versions) => {
+            if let Err(err) = save_cache(&app, &versions) {
+                warn!("Failed to cache ESP-IDF versions: {}", err);
+            }
+            Ok(versions)
+        }
+        Err(err) => match cached {
+            Some(cached) => {
+                warn!("Using cached ESP-IDF versions: {}", err);
+                Ok(IdfVersions {
+                    offline: true,
+                    ..cached
+                })
+            }
+            None => Err(err),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDF_VERSIONS_JS: &str = include_str!("../tests/data/idf_versions.js");
+
+    fn version<'a>(versions: &'a IdfVersions, name: &str) -> &'a IdfVersion {
+        versions
+            .versions
+            .iter()
+            .find(|version| version.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn js_object_to_json_cases() {
+        let cases = [
+            ("{ name: \"v5.1\" }", r#"{ "name": "v5.1" }"#),
+            ("{ text: 'ESP32' }", r#"{ "text": "ESP32" }"#),
+            ("{ 'v5.1': {} }", r#"{ "v5.1": {} }"#),
+            ("{ a_b$1 : 1 }", r#"{ "a_b$1" : 1 }"#),
+            // Keywords are values
+            (
+                "{ a: true, b: false, c: null }",
+                r#"{ "a": true, "b": false, "c": null }"#,
+            ),
+            // Quotes inside strings
+            (r"{ a: 'it\'s' }", r#"{ "a": "it's" }"#),
+            (r#"{ a: 'say "hi"' }"#, r#"{ "a": "say \"hi\"" }"#),
+            (r#"{ a: "tab\t" }"#, r#"{ "a": "tab\t" }"#),
+            // Nothing inside strings is changed
+            ("{ a: 'b: c, // d' }", r#"{ "a": "b: c, // d" }"#),
+            // Comments
+            ("{ // latest\n a: 1 }", "{ \n \"a\": 1 }"),
+            ("{ /* b: 2, */ a: 1 }", r#"{  "a": 1 }"#),
+            // Trailing commas
+            ("{ a: [1, 2,], }", r#"{ "a": [1, 2] }"#),
+            ("[{ a: 1 },\n  // end\n]", "[{ \"a\": 1 }\n  \n]"),
+            ("[1, /* end */ ]", "[1  ]"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(js_object_to_json(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn release_date_cases() {
+        let releases: HashMap<String, RawRelease> = [
+            ("v5.1", Some("2023-07-05")),
+            ("v5.1.2", Some("2023-11-17")),
+            ("v4.4", None),
+        ]
+        .into_iter()
+        .map(|(name, start_date)| {
+            let start_date = start_date.map(|date| date.to_string());
+            (name.to_string(), RawRelease { start_date })
+        })
+        .collect();
+
+        let cases = [
+            ("v5.1", Some("2023-07-05")),
+            // A release of its own wins over the branch
+            ("v5.1.2", Some("2023-11-17")),
+            ("v5.1.3", Some("2023-07-05")),
+            ("v5.1-beta1", None),
+            ("v4.4.7", None),
+            ("v5.2.1", None),
+            ("latest", None),
+            ("", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(
+                release_date(&releases, name).as_deref(),
+                expected,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn parse_versions_file() {
+        let versions = parse_idf_versions(IDF_VERSIONS_JS).unwrap();
+
+        assert_eq!(versions.versions.len(), 13);
+        assert_eq!(versions.versions[0].name, "latest");
+        assert_eq!(versions.targets.len(), 8);
+        assert_eq!(versions.targets[1].text, "ESP32-S2");
+        assert_eq!(versions.targets[1].value, "esp32s2");
+        assert!(!versions.offline);
+        assert!(versions.fetched_at > 0);
+    }
+
+    #[test]
+    fn parse_version_flags() {
+        let versions = parse_idf_versions(IDF_VERSIONS_JS).unwrap();
+
+        // name, old, end of life, pre-release
+        let cases = [
+            ("v5.2.1", false, false, false),
+            ("v5.1.2", true, false, false),
+            ("v5.3-beta1", false, false, true),
+            ("v4.2.5", false, true, false),
+            ("v3.2.5", true, true, false),
+        ];
+        for (name, old, end_of_life, pre_release) in cases {
+            let version = version(&versions, name);
+            assert_eq!(version.old, old, "{}", name);
+            assert_eq!(version.end_of_life, end_of_life, "{}", name);
+            assert_eq!(version.pre_release, pre_release, "{}", name);
+        }
+    }
+
+    #[test]
+    fn parse_version_targets_and_dates() {
+        let versions = parse_idf_versions(IDF_VERSIONS_JS).unwrap();
+
+        let cases = [
+            (
+                "v4.3.7",
+                vec!["esp32", "esp32s2", "esp32c3"],
+                Some("2021-06-30"),
+            ),
+            // Versions without targets get those of DEFAULTS
+            ("v4.1.4", vec!["esp32"], Some("2020-07-21")),
+            ("v3.2.5", vec!["esp32"], None),
+            (
+                "latest",
+                vec![
+                    "esp32", "esp32s2", "esp32s3", "esp32c3", "esp32c2", "esp32c6", "esp32h2",
+                    "esp32p4",
+                ],
+                None,
+            ),
+        ];
+        for (name, targets, date) in cases {
+            let version = version(&versions, name);
+            assert_eq!(version.supported_targets, targets, "{}", name);
+            assert_eq!(version.release_date.as_deref(), date, "{}", name);
+        }
+    }
+
+    #[test]
+    fn parse_invalid_versions() {
+        let cases = [
+            "",
+            "var DOCUMENTATION_VERSIONS = ;",
+            "var DOCUMENTATION_VERSIONS = { DEFAULTS: {} };",
+            "var DOCUMENTATION_VERSIONS = { VERSIONS: [ { name: 5 } ] };",
+        ];
+        for source in cases {
+            assert!(parse_idf_versions(source).is_err(), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,252 @@
+// Commands of the ESP serial bootloader protocol which are not exposed by espflash.
+// The loader is used on an Interface taken over from an already connected Flasher,
+// so the chip is in download mode and the flasher stub is running when requested.
+use espflash::interface::Interface;
+use espflash::targets::Chip;
+use std::io::{self, ErrorKind};
+use std::time::{Duration, Instant};
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+const SPI_FLASH_MD5: u8 = 0x13;
+// Supported only by the flasher stub
+const READ_FLASH: u8 = 0xD2;
+
+const FLASH_SECTOR_SIZE: u32 = 0x1000;
+// Number of blocks the stub may send before waiting for an acknowledgement
+const READ_FLASH_MAX_IN_FLIGHT: u32 = 64;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+// Same per megabyte timeout as esptool uses for MD5 calculation
+const MD5_TIMEOUT_PER_MB: Duration = Duration::from_secs(8);
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub struct Loader {
+    serial: Interface,
+    previous_timeout: Duration,
+    buffer: Vec<u8>,
+    position: usize,
+    status_len: usize,
+}
+
+fn timeout_for_size(timeout_per_mb: Duration, size: u32) -> Duration {
+    let timeout = timeout_per_mb.mul_f64(size as f64 / 1_000_000.0);
+    timeout.max(DEFAULT_TIMEOUT)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+impl Loader {
+    pub fn new(mut serial: Interface, chip: Chip, use_stub: bool) -> io::Result<Self> {
+        let previous_timeout = serial.serial_port().timeout();
+        serial.serial_port_mut().set_timeout(READ_TIMEOUT)?;
+        // The ESP32 ROM loader appends 4 status bytes to every response, everything else 2.
+        let status_len = if chip == Chip::Esp32 && !use_stub {
+            4
+        } else {
+            2
+        };
+        Ok(Self {
+            serial,
+            previous_timeout,
+            buffer: vec![0; 4096],
+            position: 0,
+            status_len,
+        })
+    }
+
+    // Hand the port back with the timeout it had before.
+    pub fn into_interface(mut self) -> Interface {
+        let _ = self
+            .serial
+            .serial_port_mut()
+            .set_timeout(self.previous_timeout);
+        self.serial
+    }
+
+    fn next_byte(&mut self, deadline: Instant) -> io::Result<u8> {
+        loop {
+            if self.position < self.buffer.len() {
+                self.position += 1;
+                return Ok(self.buffer[self.position - 1]);
+            }
+
+            self.buffer.resize(4096, 0);
+            match self.serial.serial_port_mut().read(&mut self.buffer) {
+                Ok(count) => {
+                    self.buffer.truncate(count);
+                    self.position = 0;
+                }
+                Err(e) if e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::Interrupted => {
+                    self.buffer.clear();
+                    self.position = 0;
+                }
+                Err(e) => return Err(e),
+            }
+
+            if self.buffer.is_empty() && Instant::now() > deadline {
+                return Err(io::Error::new(
+                    ErrorKind::TimedOut,
+                    "Timed out waiting for the loader",
+                ));
+            }
+        }
+    }
+
+    fn read_frame(&mut self, timeout: Duration) -> io::Result<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+        let mut frame = Vec::new();
+        let mut in_frame = false;
+
+        loop {
+            match self.next_byte(deadline)? {
+                SLIP_END if in_frame && !frame.is_empty() => return Ok(frame),
+                SLIP_END => in_frame = true,
+                _ if !in_frame => continue,
+                SLIP_ESC => match self.next_byte(deadline)? {
+                    SLIP_ESC_END => frame.push(SLIP_END),
+                    SLIP_ESC_ESC => frame.push(SLIP_ESC),
+                    byte => {
+                        return Err(invalid_data(format!(
+                            "Invalid SLIP escape sequence: 0x{:02x}",
+                            byte
+                        )))
+                    }
+                },
+                byte => frame.push(byte),
+            }
+        }
+    }
+
+    fn write_frame(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(data.len() + 2);
+        frame.push(SLIP_END);
+        for &byte in data {
+            match byte {
+                SLIP_END => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+                SLIP_ESC => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+                _ => frame.push(byte),
+            }
+        }
+        frame.push(SLIP_END);
+
+        let serial = self.serial.serial_port_mut();
+        serial.write_all(&frame)?;
+        serial.flush()
+    }
+
+    // Send a command and return the response data without the trailing status bytes.
+    fn command(&mut self, op: u8, data: &[u8], timeout: Duration) -> io::Result<Vec<u8>> {
+        let mut packet = vec![0x00, op];
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(&0u32.to_le_bytes());
+        packet.extend_from_slice(data);
+        self.write_frame(&packet)?;
+
+        // Skip unrelated frames, e.g. leftovers of previous commands
+        for _ in 0..100 {
+            let response = self.read_frame(timeout)?;
+            if response.len() < 8 || response[0] != 0x01 || response[1] != op {
+                continue;
+            }
+
+            let body = &response[8..];
+            if body.len() < self.status_len {
+                return Err(invalid_data(format!(
+                    "Response to command 0x{:02x} is too short",
+                    op
+                )));
+            }
+            let (body, status) = body.split_at(body.len() - self.status_len);
+            if status[0] != 0 {
+                return Err(invalid_data(format!(
+                    "Command 0x{:02x} failed with error 0x{:02x}",
+                    op, status[1]
+                )));
+            }
+            return Ok(body.to_vec());
+        }
+
+        Err(invalid_data(format!("No response to command 0x{:02x}", op)))
+    }
+
+    // MD5 digest of a flash region as calculated by the chip.
+    pub fn flash_md5(&mut self, offset: u32, size: u32) -> io::Result<[u8; 16]> {
+        let mut data = Vec::with_capacity(16);
+        for value in [offset, size, 0, 0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let timeout = timeout_for_size(MD5_TIMEOUT_PER_MB, size);
+        let body = self.command(SPI_FLASH_MD5, &data, timeout)?;
+
+        let mut digest = [0; 16];
+        match body.len() {
+            // The stub sends the raw digest
+            16 => digest.copy_from_slice(&body),
+            // The ROM loader sends the digest as hex string
+            32 => {
+                for (i, byte) in digest.iter_mut().enumerate() {
+                    let hex = std::str::from_utf8(&body[i * 2..i * 2 + 2])
+                        .map_err(|e| invalid_data(e.to_string()))?;
+                    *byte = u8::from_str_radix(hex, 16).map_err(|e| invalid_data(e.to_string()))?;
+                }
+            }
+            len => {
+                return Err(invalid_data(format!(
+                    "Unexpected MD5 response length: {}",
+                    len
+                )))
+            }
+        }
+        Ok(digest)
+    }
+
+    // Read flash contents through the stub. Every received block is passed to `on_data`,
+    // which returns false to stop reading. Returns false when reading was stopped.
+    pub fn read_flash<F>(&mut self, offset: u32, length: u32, mut on_data: F) -> io::Result<bool>
+    where
+        F: FnMut(&[u8]) -> io::Result<bool>,
+    {
+        let mut data = Vec::with_capacity(16);
+        for value in [offset, length, FLASH_SECTOR_SIZE, READ_FLASH_MAX_IN_FLIGHT] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        self.command(READ_FLASH, &data, DEFAULT_TIMEOUT)?;
+
+        let mut received: u32 = 0;
+        let mut context = md5::Context::new();
+        while received < length {
+            let block = self.read_frame(DEFAULT_TIMEOUT)?;
+            received += block.len() as u32;
+            if received < length && (block.len() as u32) < FLASH_SECTOR_SIZE {
+                return Err(invalid_data(format!(
+                    "Corrupt data, expected 0x{:x} bytes but received 0x{:x} bytes",
+                    FLASH_SECTOR_SIZE,
+                    block.len()
+                )));
+            }
+            context.consume(&block);
+
+            // Acknowledge the received bytes so the stub keeps sending
+            self.write_frame(&received.to_le_bytes())?;
+
+            if !on_data(&block)? {
+                return Ok(false);
+            }
+        }
+
+        let digest = self.read_frame(DEFAULT_TIMEOUT)?;
+        if digest[..] != context.compute().0[..] {
+            return Err(invalid_data(
+                "Digest mismatch of data read from flash".to_string(),
+            ));
+        }
+        Ok(true)
+    }
+}
@@ -4,21 +4,50 @@
 use std::sync::Mutex;
 
 mod app_state;
-use app_state::{AppState, BuilderState};
+use app_state::{cancel_jobs, finish_job, start_job, AppState, JobId, JobInfo, JobKind};
+mod backtrace;
 
+mod device_error;
+use device_error::DeviceError;
 mod download;
 
 mod console;
 use console::setup_logging;
+mod coredump;
+use coredump::{decode_core_dump, CoreDump};
 mod esp_idf;
 use esp_idf::run_install_script;
 mod external_command;
+mod flash_options;
+use flash_options::{
+    get_flash_options, load_flash_options, resolve_flash_options, set_flash_options, FlashOptions,
+};
 mod flasher;
+use flasher::{ChipInfo, DevicePartitionTable, VerifyOptions};
+mod flasher_args;
+mod idf_versions;
+use idf_versions::get_available_idf_versions;
+mod loader;
 mod monitor;
+mod monitor_decoder;
+mod monitor_filter;
+use monitor_filter::MonitorFilter;
+mod monitor_log;
+use monitor_log::{list_monitor_logs, open_monitor_log};
+mod monitor_settings;
+mod monitor_trigger;
+use monitor_trigger::TriggerRule;
+mod network;
+use network::{get_network_settings, set_network_settings};
+mod offline_bundle;
+use offline_bundle::{validate_offline_bundle, ValidatedBundles};
 mod os;
 use os::get_platform;
+mod partition_table;
+use partition_table::{load_partition_table, save_partition_table, validate_partition_table};
 mod rust;
 use rust::{check_rust_support, install_rust_support};
+mod settings;
 
 mod zip_archiver;
 use zip_archiver::{unzip, zip_dir};
@@ -39,40 +68,63 @@ enum Error {
     // PoisonError(String),
 }
 
+// Command to cancel the installation, monitors and flashing keep running
 #[tauri::command]
-async fn abort_build(state_mutex: State<'_, Mutex<AppState>>) -> Result<String, ()> {
-    let mut state = state_mutex.lock().unwrap();
-    state.builder = BuilderState::Abort;
+async fn abort_build(app: tauri::AppHandle) -> Result<String, ()> {
+    for kind in [
+        JobKind::Download,
+        JobKind::Unzip,
+        JobKind::Install,
+        JobKind::Validate,
+    ] {
+        cancel_jobs(&app, Some(kind), None);
+    }
     Ok("ok".to_string())
 }
 
+// Command to list running and recently finished jobs
+#[tauri::command]
+async fn list_jobs(state_mutex: State<'_, Mutex<AppState>>) -> Result<Vec<JobInfo>, ()> {
+    let state = state_mutex.lock().unwrap();
+    Ok(state.jobs())
+}
+
+// Command to get the status of a single job
+#[tauri::command]
+async fn get_job(
+    state_mutex: State<'_, Mutex<AppState>>,
+    id: JobId,
+) -> Result<Option<JobInfo>, ()> {
+    let state = state_mutex.lock().unwrap();
+    Ok(state.job(id))
+}
+
+// Command to cancel a single job, returns false if the job is not running
+#[tauri::command]
+async fn cancel_job(app: tauri::AppHandle, id: JobId) -> Result<bool, ()> {
+    Ok(app_state::cancel_job(&app, id))
+}
+
 // Command to copress directories into a archive file.
 #[tauri::command]
 async fn compress(
     window: Window,
     app: tauri::AppHandle,
-    state_mutex: State<'_, Mutex<AppState>>,
     source_path: String,
     target_path: String,
 ) -> Result<String, ()> {
     let method = zip::CompressionMethod::Deflated;
 
-    {
-        let mut state = state_mutex.lock().unwrap();
-        state.builder = BuilderState::Running;
-    }
+    let (job_id, cancel) = start_job(&app, JobKind::Compress, &source_path);
 
     let result = zip_dir(
         window,
-        app.clone(),
+        cancel,
         source_path.as_str(),
         target_path.as_str(),
         method,
     );
-    {
-        let mut state = state_mutex.lock().unwrap();
-        state.builder = BuilderState::Idle;
-    }
+    finish_job(&app, job_id, result.is_ok());
 
     match result {
         Ok(_) => Ok("Success".to_string()),
@@ -85,20 +137,13 @@ async fn compress(
 async fn decompress(
     window: Window,
     app: tauri::AppHandle,
-    state_mutex: State<'_, Mutex<AppState>>,
     source_path: String,
     target_path: String,
 ) -> Result<String, ()> {
-    {
-        let mut state = state_mutex.lock().unwrap();
-        state.builder = BuilderState::Running;
-    }
+    let (job_id, cancel) = start_job(&app, JobKind::Unzip, &source_path);
 
-    let result = unzip(window, app.clone(), source_path, target_path);
-    {
-        let mut state = state_mutex.lock().unwrap();
-        state.builder = BuilderState::Idle;
-    }
+    let result = unzip(window, cancel, source_path, target_path);
+    finish_job(&app, job_id, result.is_ok());
 
     match result {
         Ok(_) => Ok("Success".to_string()),
@@ -111,19 +156,12 @@ async fn decompress(
 async fn run_esp_idf_install_script(
     window: Window,
     app: tauri::AppHandle,
-    state_mutex: State<'_, Mutex<AppState>>,
     target_path: String,
 ) -> Result<String, ()> {
-    {
-        let mut state = state_mutex.lock().unwrap();
-        state.builder = BuilderState::Running;
-    }
+    let (job_id, cancel) = start_job(&app, JobKind::Install, &target_path);
 
-    let result = run_install_script(window, app.clone(), target_path);
-    {
-        let mut state = state_mutex.lock().unwrap();
-        state.builder = BuilderState::Idle;
-    }
+    let result = run_install_script(window, cancel, target_path, Vec::new()).await;
+    finish_job(&app, job_id, result.is_ok());
 
     match result {
         Ok(_) => Ok("Success".to_string()),
@@ -131,36 +169,61 @@ async fn run_esp_idf_install_script(
     }
 }
 
-// Command to download ESP-IDF to ZIP file
+// Command to install ESP-IDF and its tools from an offline bundle
+#[tauri::command]
+async fn install_esp_idf_offline(
+    window: Window,
+    app: tauri::AppHandle,
+    bundle_path: String,
+    tools_path: String,
+    target_path: String,
+) -> Result<String, String> {
+    let (job_id, cancel) = start_job(&app, JobKind::Install, &target_path);
+
+    let install_handle = tokio::spawn(offline_bundle::install_esp_idf_from_bundle(
+        window,
+        cancel,
+        bundle_path,
+        tools_path,
+        target_path,
+    ));
+
+    let result = install_handle.await;
+
+    finish_job(&app, job_id, matches!(result, Ok(Ok(_))));
+
+    match result {
+        Ok(result) => result.map(|_| "Installation finished successfully".to_string()),
+        Err(_) => Err("Installation task panicked".to_string()),
+    }
+}
+
+// Command to download ESP-IDF to ZIP file, verified when the SHA-256 is given
 #[tauri::command]
 async fn download_esp_idf(
     window: Window,
     app: tauri::AppHandle,
-    state_mutex: State<'_, Mutex<AppState>>,
     version: String,
     target_path: String,
-) -> Result<String, ()> {
-    {
-        let mut state = state_mutex.lock().unwrap();
-        state.builder = BuilderState::Running;
-    }
+    sha256: Option<String>,
+) -> Result<String, String> {
+    let (job_id, cancel) = start_job(&app, JobKind::Download, &version);
 
-    let download_handle =
-        tokio::spawn(esp_idf::download_esp_idf(window, app, version, target_path));
+    let download_handle = tokio::spawn(esp_idf::download_esp_idf(
+        window,
+        cancel,
+        version,
+        target_path,
+        sha256,
+    ));
 
     let result = download_handle.await;
 
-    {
-        let mut state = state_mutex.lock().unwrap();
-        state.builder = BuilderState::Idle;
-    }
+    finish_job(&app, job_id, matches!(result, Ok(Ok(_))));
 
     match result {
-        Ok(result) => match result {
-            Ok(_) => Ok("Download finished successfully".to_string()),
-            Err(_) => Ok("Download failed".to_string()),
-        },
-        Err(_) => Ok("Download task panicked".to_string().to_string()),
+        Ok(result) => result.map(|_| "Download finished successfully".to_string()),
+        Err(_) => Err("Download task panicked".to_string()),
     }
 }
 
@@ -184,28 +247,6 @@ async fn get_esp_idf_list() -> Result<Vec<String>, ()> {
 
 const GITHUB_REPOSITORY: &str = "espressif/esp-idf";
 
-#[tauri::command]
-async fn get_available_idf_versions() -> Result<String, String> {
-    let url = "https://dl.espressif.com/dl/esp-idf/idf_versions.js".to_string();
-    let client = reqwest::Client::builder()
-        .user_agent("esp-workbench")
-        .build()
-        .map_err(|err| format!("Failed to create reqwest client: {}", err))?;
-    let response = client
-        .get(&url)
-        .send()
-        .await
-        .map_err(|err| format!("Failed to make request: {}", err))?;
-    let js_versions_file = response
-        .text()
-        .await
-        .map_err(|err| format!("Failed to read response: {}", err))?;
-    // Find the start and end index of the object within the JavaScript file
-    let object_start = js_versions_file.find("{").ok_or("Object start not found")?;
-    let object_end = js_versions_file.rfind("}").ok_or("Object end not found")? + 1;
-    Ok(js_versions_file[object_start..object_end].to_string())
-}
-
 // Comand to get the current user home
 #[tauri::command]
 async fn get_user_home() -> Result<String, ()> {
@@ -228,45 +269,84 @@ async fn get_esp_idf_tools_dir() -> Result<String, ()> {
     Ok("C:\\Espressif".to_string())
 }
 
-use crate::monitor::monitor_port;
+use crate::monitor::{monitor_port, LineEnding, MonitorOptions, MonitorSessions, ResetAction};
 
 #[tauri::command]
 async fn start_monitor(
     window: Window,
     app: tauri::AppHandle,
-    state_mutex: State<'_, Mutex<AppState>>,
     port: String,
-) -> Result<String, ()> {
-    {
-        let mut state = state_mutex.lock().unwrap();
-        state.builder = BuilderState::Running;
-    }
+    options: Option<MonitorOptions>,
+) -> Result<String, DeviceError> {
+    let (job_id, cancel) = start_job(&app, JobKind::Monitor, &port);
 
-    let monitor_handle = tokio::spawn(monitor_port(window, app, port));
+    // The serial reads block, each monitor gets a thread instead of a runtime worker
+    let monitor_handle = tokio::task::spawn_blocking(move || {
+        monitor_port(window, cancel, port, options.unwrap_or_default())
+    });
 
     let result = monitor_handle.await;
 
-    {
-        let mut state = state_mutex.lock().unwrap();
-        state.builder = BuilderState::Idle;
-    }
+    finish_job(&app, job_id, matches!(result, Ok(Ok(_))));
 
     match result {
         Ok(result) => match result {
             Ok(_) => Ok("Monitoring finished successfully".to_string()),
-            Err(_) => Ok("Monitoring failed".to_string()),
+            Err(error) => Err(error),
         },
-        Err(_) => Ok("Monitoring task panicked".to_string().to_string()),
+        Err(_) => Err(DeviceError::Serial("Monitoring task panicked".to_string())),
     }
 }
 
 #[tauri::command]
-async fn stop_monitor(state_mutex: State<'_, Mutex<AppState>>) -> Result<String, ()> {
-    let mut state = state_mutex.lock().unwrap();
-    state.builder = BuilderState::Abort;
+async fn stop_monitor(app: tauri::AppHandle, port: Option<String>) -> Result<String, ()> {
+    // Without a port all monitors are stopped
+    cancel_jobs(&app, Some(JobKind::Monitor), port.as_deref());
     Ok("ok".to_string())
 }
 
+#[tauri::command]
+async fn send_to_monitor(
+    app: tauri::AppHandle,
+    port: String,
+    data: String,
+    line_ending: Option<LineEnding>,
+) -> Result<(), DeviceError> {
+    monitor::send_to_monitor(
+        &app,
+        &port,
+        data.as_bytes(),
+        line_ending.unwrap_or_default(),
+    )
+}
+
+#[tauri::command]
+async fn reset_monitored_port(
+    app: tauri::AppHandle,
+    port: String,
+    action: ResetAction,
+) -> Result<(), DeviceError> {
+    monitor::reset_monitored_port(&app, &port, action)
+}
+
+#[tauri::command]
+async fn set_monitor_filter(
+    app: tauri::AppHandle,
+    port: String,
+    filter: MonitorFilter,
+) -> Result<(), DeviceError> {
+    monitor::set_monitor_filter(&app, &port, filter)
+}
+
+#[tauri::command]
+async fn set_monitor_triggers(
+    app: tauri::AppHandle,
+    port: String,
+    triggers: Vec<TriggerRule>,
+) -> Result<(), DeviceError> {
+    monitor::set_monitor_triggers(&app, &port, triggers)
+}
+
 // async fn monitor_port(window: Window, app: tauri::AppHandle, port: String) -> Result<(), ()> {
 //   let state_mutex = app.get_state::<Mutex<AppState>>().unwrap();
 //   let state = state_mutex.lock().await;
@@ -279,44 +359,310 @@ async fn stop_monitor(state_mutex: State<'_, Mutex<AppState>>) -> Result<String,
 async fn start_flash(
     window: Window,
     app: tauri::AppHandle,
-    state_mutex: State<'_, Mutex<AppState>>,
     port: String,
     file_path: String,
     flash_offset: u32,
-) -> Result<String, ()> {
-    {
-        let mut state = state_mutex.lock().unwrap();
-        state.builder = BuilderState::Running;
-    }
+    verify_options: Option<VerifyOptions>,
+    flash_options: Option<FlashOptions>,
+) -> Result<String, DeviceError> {
+    let flash_options = resolve_flash_options(&app, &port, flash_options);
+    let (job_id, cancel) = start_job(&app, JobKind::Flash, &port);
 
     let flasher_handle = tokio::spawn(flasher::flash_file(
         window,
-        app,
+        cancel,
         port,
         file_path,
         flash_offset,
+        verify_options.unwrap_or_default(),
+        flash_options,
+    ));
+
+    let result = flasher_handle.await;
+
+    finish_job(&app, job_id, matches!(result, Ok(Ok(_))));
+
+    match result {
+        Ok(result) => match result {
+            Ok(_) => Ok("Flashing finished successfully".to_string()),
+            Err(error) => Err(error),
+        },
+        Err(_) => Err(DeviceError::Flash("Flashing task panicked".to_string())),
+    }
+}
+
+// Command to flash all images of an ESP-IDF build directory or its flasher_args.json
+#[tauri::command]
+async fn start_flash_build(
+    window: Window,
+    app: tauri::AppHandle,
+    port: String,
+    build_path: String,
+    verify_options: Option<VerifyOptions>,
+    flash_options: Option<FlashOptions>,
+) -> Result<String, DeviceError> {
+    let flash_options = resolve_flash_options(&app, &port, flash_options);
+    let (job_id, cancel) = start_job(&app, JobKind::Flash, &port);
+
+    let flasher_handle = tokio::spawn(flasher::flash_build(
+        window,
+        cancel,
+        port,
+        build_path,
+        verify_options.unwrap_or_default(),
+        flash_options,
+    ));
+
+    let result = flasher_handle.await;
+
+    finish_job(&app, job_id, matches!(result, Ok(Ok(_))));
+
+    match result {
+        Ok(result) => match result {
+            Ok(_) => Ok("Flashing finished successfully".to_string()),
+            Err(error) => Err(error),
+        },
+        Err(_) => Err(DeviceError::Flash("Flashing task panicked".to_string())),
+    }
+}
+
+// Command to erase the whole flash
+#[tauri::command]
+async fn erase_flash(
+    window: Window,
+    app: tauri::AppHandle,
+    port: String,
+) -> Result<String, DeviceError> {
+    let (job_id, cancel) = start_job(&app, JobKind::Flash, &port);
+
+    let flash_options = load_flash_options(&app, &port);
+    let flasher_handle = tokio::spawn(flasher::erase_flash(window, cancel, port, flash_options));
+
+    let result = flasher_handle.await;
+
+    finish_job(&app, job_id, matches!(result, Ok(Ok(_))));
+
+    match result {
+        Ok(result) => match result {
+            Ok(_) => Ok("Erasing finished successfully".to_string()),
+            Err(error) => Err(error),
+        },
+        Err(_) => Err(DeviceError::Flash("Erasing task panicked".to_string())),
+    }
+}
+
+// Command to erase a sector aligned region of flash
+#[tauri::command]
+async fn erase_region(
+    window: Window,
+    app: tauri::AppHandle,
+    port: String,
+    offset: u32,
+    size: u32,
+) -> Result<String, DeviceError> {
+    let (job_id, cancel) = start_job(&app, JobKind::Flash, &port);
+
+    let flash_options = load_flash_options(&app, &port);
+    let flasher_handle = tokio::spawn(flasher::erase_region(
+        window,
+        cancel,
+        port,
+        offset,
+        size,
+        flash_options,
     ));
 
     let result = flasher_handle.await;
 
-    {
-        let mut state = state_mutex.lock().unwrap();
-        state.builder = BuilderState::Idle;
+    finish_job(&app, job_id, matches!(result, Ok(Ok(_))));
+
+    match result {
+        Ok(result) => match result {
+            Ok(_) => Ok("Erasing finished successfully".to_string()),
+            Err(error) => Err(error),
+        },
+        Err(_) => Err(DeviceError::Flash("Erasing task panicked".to_string())),
     }
+}
+
+// Command to dump a region of flash into a file
+#[tauri::command]
+async fn read_flash(
+    window: Window,
+    app: tauri::AppHandle,
+    port: String,
+    offset: u32,
+    length: u32,
+    output_path: String,
+) -> Result<String, DeviceError> {
+    let (job_id, cancel) = start_job(&app, JobKind::Flash, &port);
+
+    let flash_options = load_flash_options(&app, &port);
+    let flasher_handle = tokio::spawn(flasher::read_flash(
+        window,
+        cancel,
+        port,
+        offset,
+        length,
+        output_path,
+        flash_options,
+    ));
+
+    let result = flasher_handle.await;
+
+    finish_job(&app, job_id, matches!(result, Ok(Ok(_))));
+
+    match result {
+        Ok(result) => match result {
+            Ok(_) => Ok("Reading flash finished successfully".to_string()),
+            Err(error) => Err(error),
+        },
+        Err(_) => Err(DeviceError::Flash(
+            "Reading flash task panicked".to_string(),
+        )),
+    }
+}
+
+// Command to write a file into the partition with the given name
+#[tauri::command]
+async fn flash_partition(
+    window: Window,
+    app: tauri::AppHandle,
+    port: String,
+    name: String,
+    file_path: String,
+    verify_options: Option<VerifyOptions>,
+    flash_options: Option<FlashOptions>,
+) -> Result<String, DeviceError> {
+    let flash_options = resolve_flash_options(&app, &port, flash_options);
+    let (job_id, cancel) = start_job(&app, JobKind::Flash, &port);
+
+    let flasher_handle = tokio::spawn(flasher::flash_partition(
+        window,
+        cancel,
+        port,
+        name,
+        file_path,
+        verify_options.unwrap_or_default(),
+        flash_options,
+    ));
+
+    let result = flasher_handle.await;
+
+    finish_job(&app, job_id, matches!(result, Ok(Ok(_))));
 
     match result {
         Ok(result) => match result {
             Ok(_) => Ok("Flashing finished successfully".to_string()),
-            Err(_) => Ok("flashing failed".to_string()),
+            Err(error) => Err(error),
+        },
+        Err(_) => Err(DeviceError::Flash("Flashing task panicked".to_string())),
+    }
+}
+
+// Command to erase the partition with the given name
+#[tauri::command]
+async fn erase_partition(
+    window: Window,
+    app: tauri::AppHandle,
+    port: String,
+    name: String,
+) -> Result<String, DeviceError> {
+    let (job_id, cancel) = start_job(&app, JobKind::Flash, &port);
+
+    let flash_options = load_flash_options(&app, &port);
+    let flasher_handle = tokio::spawn(flasher::erase_partition(
+        window,
+        cancel,
+        port,
+        name,
+        flash_options,
+    ));
+
+    let result = flasher_handle.await;
+
+    finish_job(&app, job_id, matches!(result, Ok(Ok(_))));
+
+    match result {
+        Ok(result) => match result {
+            Ok(_) => Ok("Erasing finished successfully".to_string()),
+            Err(error) => Err(error),
         },
-        Err(_) => Ok("Flashing task panicked".to_string().to_string()),
+        Err(_) => Err(DeviceError::Flash("Erasing task panicked".to_string())),
     }
 }
 
+// Command to dump the partition with the given name into a file
 #[tauri::command]
-async fn stop_flash(state_mutex: State<'_, Mutex<AppState>>) -> Result<String, ()> {
-    let mut state = state_mutex.lock().unwrap();
-    state.builder = BuilderState::Abort;
+async fn read_partition(
+    window: Window,
+    app: tauri::AppHandle,
+    port: String,
+    name: String,
+    output_path: String,
+) -> Result<String, DeviceError> {
+    let (job_id, cancel) = start_job(&app, JobKind::Flash, &port);
+
+    let flash_options = load_flash_options(&app, &port);
+    let flasher_handle = tokio::spawn(flasher::read_partition(
+        window,
+        cancel,
+        port,
+        name,
+        output_path,
+        flash_options,
+    ));
+
+    let result = flasher_handle.await;
+
+    finish_job(&app, job_id, matches!(result, Ok(Ok(_))));
+
+    match result {
+        Ok(result) => match result {
+            Ok(_) => Ok("Reading partition finished successfully".to_string()),
+            Err(error) => Err(error),
+        },
+        Err(_) => Err(DeviceError::Flash(
+            "Reading partition task panicked".to_string(),
+        )),
+    }
+}
+
+// Command to read the core dump stored on the device and decode it
+#[tauri::command]
+async fn read_core_dump(
+    window: Window,
+    app: tauri::AppHandle,
+    port: String,
+    elf_path: Option<String>,
+) -> Result<Option<CoreDump>, DeviceError> {
+    let (job_id, cancel) = start_job(&app, JobKind::Flash, &port);
+
+    let flash_options = load_flash_options(&app, &port);
+    let flasher_handle = tokio::spawn(coredump::read_core_dump(
+        window,
+        cancel,
+        port,
+        elf_path,
+        flash_options,
+    ));
+
+    let result = flasher_handle.await;
+
+    finish_job(&app, job_id, matches!(result, Ok(Ok(_))));
+
+    match result {
+        Ok(result) => result,
+        Err(_) => Err(DeviceError::Flash(
+            "Reading core dump task panicked".to_string(),
+        )),
+    }
+}
+
+#[tauri::command]
+async fn stop_flash(app: tauri::AppHandle, port: Option<String>) -> Result<String, ()> {
+    // Without a port flashing is stopped on all ports
+    cancel_jobs(&app, Some(JobKind::Flash), port.as_deref());
     Ok("ok".to_string())
 }
 
@@ -368,25 +714,73 @@ async fn get_connected_serial_devices() -> Vec<ConnectedPort> {
     esp32s
 }
 
+// Command to identify the chip connected to the port
+#[tauri::command]
+async fn get_chip_info(app: tauri::AppHandle, port: String) -> Result<ChipInfo, DeviceError> {
+    let flash_options = load_flash_options(&app, &port);
+    flasher::get_chip_info(port, flash_options).await
+}
+
+// Command to read and validate the partition table stored on the device
+#[tauri::command]
+async fn read_partition_table(
+    app: tauri::AppHandle,
+    port: String,
+) -> Result<DevicePartitionTable, DeviceError> {
+    let flash_options = load_flash_options(&app, &port);
+    flasher::read_partition_table(port, flash_options).await
+}
+
 fn main() {
     tauri::Builder::default()
         .manage(Mutex::new(AppState::default()))
+        .manage(MonitorSessions::default())
+        .manage(ValidatedBundles::default())
         .invoke_handler(tauri::generate_handler![
             compress,
             decompress,
             download_esp_idf,
             get_connected_serial_devices,
+            get_chip_info,
             get_disk_usage,
             get_user_home,
             get_esp_idf_list,
             get_esp_idf_tools_dir,
             get_available_idf_versions,
             abort_build,
+            list_jobs,
+            get_job,
+            cancel_job,
             run_esp_idf_install_script,
+            install_esp_idf_offline,
+            validate_offline_bundle,
             start_flash,
+            start_flash_build,
+            read_flash,
+            erase_flash,
+            erase_region,
+            flash_partition,
+            erase_partition,
+            read_partition,
+            read_partition_table,
+            read_core_dump,
+            decode_core_dump,
+            load_partition_table,
+            validate_partition_table,
+            save_partition_table,
+            get_flash_options,
+            set_flash_options,
+            get_network_settings,
+            set_network_settings,
             stop_flash,
             start_monitor,
             stop_monitor,
+            send_to_monitor,
+            reset_monitored_port,
+            set_monitor_filter,
+            set_monitor_triggers,
+            list_monitor_logs,
+            open_monitor_log,
             check_rust_support,
             install_rust_support,
             get_platform
@@ -1,41 +1,216 @@
-use tauri::{Manager, Window};
+use tauri::{AppHandle, Manager, Window};
 
-use crate::app_state::{AppState, BuilderState};
+use crate::app_state::CancellationToken;
+use crate::backtrace::Symbols;
+use crate::device_error::{report_error, DeviceError};
+use crate::flash_options::{load_flash_options, USB_SERIAL_JTAG_PID};
+use crate::monitor_decoder::{Line, LineDecoder, Span, Style};
+use crate::monitor_filter::{LineFilter, LineInfo, LogLevel, MonitorFilter};
+use crate::monitor_log::{save_snapshot, timestamp, MonitorLog, MonitorLogOptions};
+use crate::monitor_settings::{detect_baud, MonitorSettings};
+use crate::monitor_trigger::{TriggerAction, TriggerRule, TriggerSet};
+use espflash::connection::reset_after_flash;
 use espflash::interface::Interface;
 use serialport::available_ports;
-use serialport::SerialPortInfo;
+use serialport::{SerialPortInfo, SerialPortType};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
 use std::io;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::Instant;
 use std::{io::ErrorKind, time::Duration};
 
-fn normalized<I>(iter: I) -> impl Iterator<Item = u8>
-where
-    I: Iterator<Item = u8>,
-{
-    iter.map(|byte| match byte {
-        0..=31 | 127 => b'?', // replace control characters with '?'
-        _ => byte,
-    })
+// A line without line break is shown after the port was silent for this long
+const PARTIAL_LINE_DELAY: Duration = Duration::from_millis(100);
+// How often to look for a disconnected device
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(250);
+// Triggers only see the start of longer lines
+const MAX_TRIGGER_LINE_LENGTH: usize = 64 * 1024;
+
+// Marker lines for resets, magenta like the markers of idf.py monitor
+const MARKER_STYLE: Style = Style {
+    fg: Some(5),
+    bg: None,
+    bold: true,
+};
+
+// Port opened by a running monitor. The reader loop only holds the lock for a
+// single read with a short timeout, so writes from send_to_monitor get through.
+pub struct MonitorSession {
+    serial: Mutex<Interface>,
+    // USB PID deciding which reset sequence the chip needs
+    pid: u16,
+    // Lines to insert into the monitor stream, e.g. when the chip was reset
+    markers: Mutex<Vec<Line>>,
+    rules: Arc<MonitorRules>,
 }
 
-fn handle_serial(buff: &[u8], window: &Window) {
-    // println!("Serial: {:?}", buff);
-    // Normalize the buffer content
-    let text: Vec<u8> = normalized(buff.iter().copied()).collect();
-    let text = String::from_utf8_lossy(&text).to_string();
+// Filter and triggers of a monitor, shared with the reader loop so they can be
+// changed while monitoring.
+struct MonitorRules {
+    filter: Mutex<LineFilter>,
+    triggers: Mutex<TriggerSet>,
+}
 
-    // Emit the line to the frontend
-    let payload = Payload {
-        pct: format!("{}\r\n", text),
-    };
-    // println!("payload: {:?}", payload);
-    window.emit("monitor-event", payload).unwrap();
+// Monitored ports. A port is reserved before it is opened, the session is
+// added once the port is set up.
+#[derive(Default)]
+pub struct MonitorSessions(Mutex<HashMap<String, Option<Arc<MonitorSession>>>>);
+
+impl MonitorSessions {
+    // One monitor per port, None when the port is already taken
+    fn reserve<'a>(&'a self, port: &'a str) -> Option<SessionSlot<'a>> {
+        match self.0.lock().unwrap().entry(port.to_string()) {
+            Entry::Occupied(_) => None,
+            Entry::Vacant(entry) => {
+                entry.insert(None);
+                Some(SessionSlot {
+                    sessions: self,
+                    port,
+                })
+            }
+        }
+    }
+
+    fn get(&self, port: &str) -> Result<Arc<MonitorSession>, DeviceError> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(port)
+            .cloned()
+            .flatten()
+            .ok_or_else(|| DeviceError::InvalidInput(format!("Port {} is not monitored", port)))
+    }
 }
 
-fn is_abort_state(app: tauri::AppHandle) -> bool {
-    let state_mutex = app.state::<Mutex<AppState>>();
-    let state = state_mutex.lock().unwrap();
-    matches!(state.builder, BuilderState::Abort)
+// Reservation of a port, released when the monitor ends.
+struct SessionSlot<'a> {
+    sessions: &'a MonitorSessions,
+    port: &'a str,
+}
+
+impl SessionSlot<'_> {
+    fn open(&self, serial: Interface, pid: u16, rules: Arc<MonitorRules>) -> Arc<MonitorSession> {
+        let session = Arc::new(MonitorSession {
+            serial: Mutex::new(serial),
+            pid,
+            markers: Mutex::new(Vec::new()),
+            rules,
+        });
+        self.sessions
+            .0
+            .lock()
+            .unwrap()
+            .insert(self.port.to_string(), Some(session.clone()));
+        session
+    }
+}
+
+impl Drop for SessionSlot<'_> {
+    fn drop(&mut self) {
+        self.sessions.0.lock().unwrap().remove(self.port);
+    }
+}
+
+// DTR/RTS control of the chip while monitoring.
+// hard_reset: restart the application.
+// bootloader: restart into the ROM download mode.
+// hold_reset: keep the chip in reset until release.
+#[derive(Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResetAction {
+    HardReset,
+    Bootloader,
+    HoldReset,
+    Release,
+}
+
+impl ResetAction {
+    fn marker(&self) -> &'static str {
+        match self {
+            ResetAction::HardReset => "--- Hard reset ---",
+            ResetAction::Bootloader => "--- Reset into download mode ---",
+            ResetAction::HoldReset => "--- Holding chip in reset ---",
+            ResetAction::Release => "--- Released chip from reset ---",
+        }
+    }
+
+    // Same sequences as espflash uses, RTS drives EN and DTR drives IO0
+    // through the auto-reset circuit of the boards.
+    fn perform(&self, serial: &mut Interface, pid: u16) -> serialport::Result<()> {
+        let usb_jtag = pid == USB_SERIAL_JTAG_PID;
+        match self {
+            ResetAction::HardReset => reset_after_flash(serial, pid),
+            ResetAction::Bootloader if usb_jtag => {
+                serial.write_data_terminal_ready(false)?;
+                serial.write_request_to_send(false)?;
+                sleep(Duration::from_millis(100));
+                serial.write_data_terminal_ready(true)?;
+                serial.write_request_to_send(false)?;
+                sleep(Duration::from_millis(100));
+                serial.write_request_to_send(true)?;
+                serial.write_data_terminal_ready(false)?;
+                serial.write_request_to_send(true)?;
+                sleep(Duration::from_millis(100));
+                serial.write_data_terminal_ready(false)?;
+                serial.write_request_to_send(false)
+            }
+            ResetAction::Bootloader => {
+                serial.write_data_terminal_ready(false)?;
+                serial.write_request_to_send(true)?;
+                sleep(Duration::from_millis(100));
+                serial.write_data_terminal_ready(true)?;
+                serial.write_request_to_send(false)?;
+                sleep(Duration::from_millis(50));
+                serial.write_data_terminal_ready(false)
+            }
+            ResetAction::HoldReset => {
+                serial.write_data_terminal_ready(false)?;
+                serial.write_request_to_send(true)
+            }
+            ResetAction::Release => serial.write_request_to_send(false),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    None,
+    Cr,
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            LineEnding::None => b"",
+            LineEnding::Cr => b"\r",
+            LineEnding::Lf => b"\n",
+            LineEnding::CrLf => b"\r\n",
+        }
+    }
+}
+
+fn emit_line(window: &Window, port: &str, line: Line, info: LineInfo) {
+    let pct = if line.partial {
+        line.text
+    } else {
+        format!("{}\r\n", line.text)
+    };
+    let payload = Payload {
+        port: port.to_string(),
+        pct,
+        spans: line.spans,
+        partial: line.partial,
+        level: info.level,
+        tag: info.tag,
+        highlight: info.highlight,
+    };
+    let _ = window.emit("monitor-event", payload);
 }
 
 pub fn get_serial_port_info(port_name: &str) -> io::Result<SerialPortInfo> {
@@ -48,14 +223,56 @@ pub fn get_serial_port_info(port_name: &str) -> io::Result<SerialPortInfo> {
     Err(io::Error::new(io::ErrorKind::NotFound, "Port not found"))
 }
 
+// pct holds the plain text of the line, spans the same text with its colors.
+// level and tag are set for ESP-IDF log lines.
 #[derive(Clone, serde::Serialize)]
 struct Payload {
+    port: String,
     pct: String,
+    spans: Vec<Span>,
+    partial: bool,
+    level: Option<LogLevel>,
+    tag: Option<String>,
+    highlight: Option<String>,
 }
 
-pub async fn monitor_port(window: Window, app: tauri::AppHandle, port: String) -> Result<(), ()> {
-    // let state_mutex = app.get_state::<Mutex<AppState>>().unwrap();
+// Sent as monitor-trigger event when a trigger rule matched.
+#[derive(Clone, serde::Serialize)]
+struct TriggerPayload {
+    port: String,
+    name: String,
+    line: String,
+    // Path of the saved snapshot
+    snapshot: Option<String>,
+}
+
+// Everything start_monitor can be given besides the port.
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+pub struct MonitorOptions {
+    // ELF of the running application to decode backtraces
+    pub elf_path: Option<String>,
+    pub settings: MonitorSettings,
+    pub filter: MonitorFilter,
+    pub triggers: Vec<TriggerRule>,
+    // Log to a file when set
+    pub log: Option<MonitorLogOptions>,
+}
 
+// Blocks until monitoring ends, runs on a thread of its own.
+pub fn monitor_port(
+    window: Window,
+    cancel: CancellationToken,
+    port: String,
+    options: MonitorOptions,
+) -> Result<(), DeviceError> {
+    let MonitorOptions {
+        elf_path,
+        settings,
+        filter,
+        triggers,
+        log: log_options,
+    } = options;
     // create necessary ConnectArgs and Config
     // let connect_args = ConnectArgs {
     //   port: Some(port.co),
@@ -63,17 +280,21 @@ pub async fn monitor_port(window: Window, app: tauri::AppHandle, port: String) -
     //   no_stub: false,
     // };
 
-    let dtr = Some(1);
-    let rts = Some(0);
+    // One monitor per port, the port is busy while it is monitored
+    let sessions = window.state::<MonitorSessions>();
+    let Some(slot) = sessions.reserve(&port) else {
+        return Err(report_error(&window, DeviceError::PortBusy(port.clone())));
+    };
+    settings.validate().map_err(|e| report_error(&window, e))?;
+    let rules = Arc::new(MonitorRules {
+        filter: Mutex::new(LineFilter::new(filter).map_err(|e| report_error(&window, e))?),
+        triggers: Mutex::new(TriggerSet::new(triggers).map_err(|e| report_error(&window, e))?),
+    });
 
-    let port_info = get_serial_port_info(port.as_str()).unwrap();
+    let port_info = get_serial_port_info(port.as_str())
+        .map_err(|e| report_error(&window, DeviceError::from_io(&port, e)))?;
 
-    let mut serial = Interface::new(&port_info, dtr, rts).unwrap();
-    serial.serial_port_mut().set_baud_rate(115200).unwrap();
-    serial
-        .serial_port_mut()
-        .set_timeout(Duration::from_millis(5))
-        .unwrap();
+    let mut serial = open_interface(&port_info, &settings).map_err(|e| report_error(&window, e))?;
     //  let port_x = UsbPortInfo {
     //   vid: 0,
     //   pid: 0,
@@ -89,34 +310,433 @@ pub async fn monitor_port(window: Window, app: tauri::AppHandle, port: String) -
     //   false,
     // ).unwrap();
 
-    let mut buff = [0; 1024];
     // let mut serial = flasher.into_interface();
 
-    let payload = Payload {
-        pct: format!("{}\r\n", "Starting monitoring"),
+    let mut output = MonitorOutput {
+        window: window.clone(),
+        symbols: None,
+        log: None,
+        port: port.clone(),
+        rules,
+        continued_line: None,
+        partial_text: String::new(),
+        history: VecDeque::new(),
+        actions: Vec::new(),
+    };
+
+    // Monitoring works without the ELF, only backtraces are not decoded
+    if let Some(elf_path) = elf_path {
+        match Symbols::load(&elf_path) {
+            Ok(symbols) => output.symbols = Some(symbols),
+            Err(e) => {
+                report_error(&window, e);
+                output.line(Line::plain("Backtraces will not be decoded"));
+            }
+        }
+    }
+
+    if let Some(log_options) = log_options {
+        let log = MonitorLog::create(&window.app_handle(), &port, &log_options)
+            .map_err(|e| report_error(&window, e))?;
+        output.line(Line::plain(&format!("Logging to {}", log.path().display())));
+        output.log = Some(log);
+    }
+
+    if settings.auto_baud {
+        output.line(Line::plain("Detecting baud rate"));
+        let detected = detect_baud(serial.serial_port_mut(), &cancel)
+            .map_err(|e| report_error(&window, DeviceError::from_serial(&port, e)))?;
+        let baud = match detected {
+            Some(baud) => {
+                output.line(Line::plain(&format!("Detected baud rate {}", baud)));
+                baud
+            }
+            None => {
+                output.line(Line::plain(&format!(
+                    "No data received, using baud rate {}",
+                    settings.baud
+                )));
+                settings.baud
+            }
+        };
+        serial
+            .serial_port_mut()
+            .set_baud_rate(baud)
+            .map_err(|e| report_error(&window, DeviceError::from_serial(&port, e)))?;
+    }
+    let reconnect = settings.reconnect.then(|| Reconnect {
+        baud: serial.serial_port().baud_rate().unwrap_or(settings.baud),
+        port_info: port_info.clone(),
+        settings: settings.clone(),
+    });
+
+    // The reset sequence follows the reset mode chosen for flashing the port
+    let pid = match &port_info.port_type {
+        SerialPortType::UsbPort(usb_info) => usb_info.pid,
+        _ => 0,
     };
-    window.emit("monitor-event", payload).unwrap();
+    let pid = load_flash_options(&window.app_handle(), &port)
+        .before
+        .reset_pid(pid);
+
+    let session = slot.open(serial, pid, output.rules.clone());
+    let result = read_loop(&mut output, &cancel, &port, &session, reconnect.as_ref());
+    output.flush_log();
+    result
+}
+
+// Where the received data goes: the frontend, the backtrace decoder and the log file.
+// The log gets all lines, the frontend only those passing the filter.
+struct MonitorOutput {
+    window: Window,
+    symbols: Option<Symbols>,
+    log: Option<MonitorLog>,
+    port: String,
+    rules: Arc<MonitorRules>,
+    // Filter result of a partial line, applied to the rest of the line
+    continued_line: Option<(bool, LineInfo)>,
+    // Text of the partial lines emitted so far, triggers match whole lines
+    partial_text: String,
+    // Last received lines with timestamps for trigger snapshots
+    history: VecDeque<String>,
+    // Trigger actions to be done by the reader loop
+    actions: Vec<(String, TriggerAction)>,
+}
+
+impl MonitorOutput {
+    fn raw(&mut self, data: &[u8]) {
+        if let Some(Err(e)) = self.log.as_mut().map(|log| log.write_raw(data)) {
+            self.log_failed(e);
+        }
+    }
+
+    fn line(&mut self, line: Line) {
+        let (shown, info) = if !line.device {
+            (true, LineInfo::default())
+        } else if let Some(continued_line) = self.continued_line.take() {
+            continued_line
+        } else {
+            let filter = self.rules.filter.lock().unwrap();
+            let info = filter.info(&line.text);
+            (filter.is_shown(&line.text, &info), info)
+        };
+        if line.device && line.partial {
+            self.continued_line = Some((shown, info.clone()));
+        }
+        if line.device {
+            if self.partial_text.len() < MAX_TRIGGER_LINE_LENGTH {
+                self.partial_text.push_str(&line.text);
+            }
+            if !line.partial {
+                let text = std::mem::take(&mut self.partial_text);
+                self.check_triggers(&text);
+            }
+        }
+
+        let decoded = match (&self.symbols, line.partial) {
+            (Some(symbols), false) => symbols.decode(&line.text),
+            _ => Vec::new(),
+        };
+        for line in std::iter::once(line).chain(decoded) {
+            if let Some(Err(e)) = self.log.as_mut().map(|log| log.write_line(&line)) {
+                self.log_failed(e);
+            }
+            if shown {
+                emit_line(&self.window, &self.port, line, info.clone());
+            }
+        }
+    }
+
+    fn check_triggers(&mut self, text: &str) {
+        let (history_size, fired) = {
+            let mut triggers = self.rules.triggers.lock().unwrap();
+            (triggers.history_size(), triggers.matches(text))
+        };
+        self.history.push_back(format!("{} {}", timestamp(), text));
+        while self.history.len() > history_size {
+            self.history.pop_front();
+        }
+
+        for rule in fired {
+            let mut snapshot = None;
+            for action in &rule.actions {
+                match action {
+                    TriggerAction::Snapshot => {
+                        let skip = self.history.len().saturating_sub(rule.snapshot_lines);
+                        match save_snapshot(
+                            &self.window.app_handle(),
+                            &self.port,
+                            &rule.name,
+                            self.history.iter().skip(skip),
+                        ) {
+                            Ok(path) => snapshot = Some(path.display().to_string()),
+                            Err(e) => {
+                                report_error(&self.window, e);
+                            }
+                        }
+                    }
+                    action => self.actions.push((rule.name.clone(), *action)),
+                }
+            }
+
+            let payload = TriggerPayload {
+                port: self.port.clone(),
+                name: rule.name,
+                line: text.to_string(),
+                snapshot,
+            };
+            let _ = self.window.emit("monitor-trigger", payload);
+        }
+    }
+
+    fn flush_log(&mut self) {
+        if let Some(Err(e)) = self.log.as_mut().map(|log| log.flush()) {
+            self.log_failed(e);
+        }
+    }
+
+    // Monitoring goes on without the log, e.g. when the disk is full
+    fn log_failed(&mut self, error: DeviceError) {
+        self.log = None;
+        report_error(&self.window, error);
+        emit_line(
+            &self.window,
+            &self.port,
+            Line::plain("Logging stopped"),
+            LineInfo::default(),
+        );
+    }
+}
+
+fn open_interface(
+    port_info: &SerialPortInfo,
+    settings: &MonitorSettings,
+) -> Result<Interface, DeviceError> {
+    let port = &port_info.port_name;
+    let dtr = Some(1);
+    let rts = Some(0);
+
+    let mut serial =
+        Interface::new(port_info, dtr, rts).map_err(|e| DeviceError::from_interface(port, e))?;
+    settings
+        .apply(serial.serial_port_mut())
+        .map_err(|e| DeviceError::from_serial(port, e))?;
+    Ok(serial)
+}
+
+// What is needed to open the port again after the device disappeared.
+struct Reconnect {
+    port_info: SerialPortInfo,
+    settings: MonitorSettings,
+    // Baud rate in use, it may have been detected
+    baud: u32,
+}
+
+impl Reconnect {
+    // Boards with native USB get a new port when they are reset, the device is
+    // recognized by its USB IDs and serial number.
+    fn is_same_device(&self, candidate: &SerialPortInfo) -> bool {
+        match (&self.port_info.port_type, &candidate.port_type) {
+            (SerialPortType::UsbPort(device), SerialPortType::UsbPort(other)) => {
+                device.vid == other.vid
+                    && device.pid == other.pid
+                    && device.serial_number == other.serial_number
+                    // Without a serial number the port could belong to another board
+                    && (device.serial_number.is_some()
+                        || self.port_info.port_name == candidate.port_name)
+            }
+            _ => self.port_info.port_name == candidate.port_name,
+        }
+    }
+
+    // Wait until the device is back and open it, None when monitoring was stopped.
+    fn wait_for_device(&self, cancel: &CancellationToken) -> Option<(Interface, String)> {
+        while !cancel.is_cancelled() {
+            sleep(RECONNECT_INTERVAL);
+
+            let Some(port_info) = available_ports()
+                .unwrap_or_default()
+                .into_iter()
+                .find(|candidate| self.is_same_device(candidate))
+            else {
+                continue;
+            };
+            // Opening fails while the system is still setting up the port
+            if let Ok(mut serial) = open_interface(&port_info, &self.settings) {
+                if serial.serial_port_mut().set_baud_rate(self.baud).is_ok() {
+                    return Some((serial, port_info.port_name));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn read_loop(
+    output: &mut MonitorOutput,
+    cancel: &CancellationToken,
+    port: &str,
+    session: &MonitorSession,
+    reconnect: Option<&Reconnect>,
+) -> Result<(), DeviceError> {
+    let mut buff = [0; 1024];
+    let mut decoder = LineDecoder::default();
+    let mut last_read = Instant::now();
+
+    output.line(Line::plain("Starting monitoring"));
     loop {
-        let read_count = match serial.serial_port_mut().read(&mut buff) {
-            Ok(count) => Ok(count),
-            Err(e) if e.kind() == ErrorKind::TimedOut => Ok(0),
-            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
-            err => err,
+        let (markers, read_result) = {
+            let mut serial = session.serial.lock().unwrap();
+            let markers = std::mem::take(&mut *session.markers.lock().unwrap());
+            (markers, serial.serial_port_mut().read(&mut buff))
+        };
+        if !markers.is_empty() {
+            if let Some(line) = decoder.flush_partial() {
+                output.line(line);
+            }
+            for marker in markers {
+                output.line(marker);
+            }
         }
-        .unwrap();
+        let read_count = match read_result {
+            Ok(count) => count,
+            Err(e) if e.kind() == ErrorKind::TimedOut => 0,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                // The board was unplugged or reset and re-enumerated
+                if let Some(line) = decoder.flush_partial() {
+                    output.line(line);
+                }
+                let Some(reconnect) = reconnect else {
+                    output.line(Line::plain("Monitoring stopped"));
+                    return Err(report_error(&output.window, DeviceError::from_io(port, e)));
+                };
+
+                output.line(Line::styled(
+                    "--- Device disconnected, waiting for it to reappear ---",
+                    MARKER_STYLE,
+                ));
+                match reconnect.wait_for_device(cancel) {
+                    Some((serial, port_name)) => {
+                        *session.serial.lock().unwrap() = serial;
+                        output.line(Line::styled(
+                            &format!("--- Reconnected on {} ---", port_name),
+                            MARKER_STYLE,
+                        ));
+                        last_read = Instant::now();
+                        continue;
+                    }
+                    None => 0,
+                }
+            }
+        };
 
         if read_count > 0 {
-            handle_serial(&buff[0..read_count], &window);
+            last_read = Instant::now();
+            output.raw(&buff[0..read_count]);
+            for line in decoder.feed(&buff[0..read_count]) {
+                output.line(line);
+            }
+
+            for (name, action) in std::mem::take(&mut output.actions) {
+                match action {
+                    TriggerAction::Stop => {
+                        output.line(Line::plain(&format!(
+                            "Monitoring stopped by trigger {}",
+                            name
+                        )));
+                        return Ok(());
+                    }
+                    TriggerAction::Reset => {
+                        output.line(Line::styled(
+                            &format!("--- Hard reset by trigger {} ---", name),
+                            MARKER_STYLE,
+                        ));
+                        let mut serial = session.serial.lock().unwrap();
+                        ResetAction::HardReset
+                            .perform(&mut serial, session.pid)
+                            .map_err(|e| {
+                                report_error(&output.window, DeviceError::from_serial(port, e))
+                            })?;
+                    }
+                    TriggerAction::Snapshot => {}
+                }
+            }
+        } else if last_read.elapsed() >= PARTIAL_LINE_DELAY {
+            if let Some(line) = decoder.flush_partial() {
+                output.line(line);
+            }
+            // Keep the log file current while the device is quiet
+            output.flush_log();
         }
 
-        if is_abort_state(app.clone()) {
-            let payload = Payload {
-                pct: format!("{}\r\n","Monitoring stopped"),
-            };
-            window.emit("monitor-event", payload).unwrap();
+        if cancel.is_cancelled() {
+            if let Some(line) = decoder.flush_partial() {
+                output.line(line);
+            }
+            output.line(Line::plain("Monitoring stopped"));
             break;
         }
     }
 
     Ok(())
 }
+
+// Write to the port of a running monitor, the line ending is appended to the data.
+pub fn send_to_monitor(
+    app: &AppHandle,
+    port: &str,
+    data: &[u8],
+    line_ending: LineEnding,
+) -> Result<(), DeviceError> {
+    let session = app.state::<MonitorSessions>().get(port)?;
+    let mut serial = session.serial.lock().unwrap();
+    let serial_port = serial.serial_port_mut();
+    serial_port
+        .write_all(data)
+        .and_then(|_| serial_port.write_all(line_ending.as_bytes()))
+        .and_then(|_| serial_port.flush())
+        .map_err(|e| DeviceError::from_io(port, e))
+}
+
+// Reset the chip of a monitored port, a marker line shows when it happened.
+pub fn reset_monitored_port(
+    app: &AppHandle,
+    port: &str,
+    action: ResetAction,
+) -> Result<(), DeviceError> {
+    let session = app.state::<MonitorSessions>().get(port)?;
+    let mut serial = session.serial.lock().unwrap();
+    session
+        .markers
+        .lock()
+        .unwrap()
+        .push(Line::styled(action.marker(), MARKER_STYLE));
+    action
+        .perform(&mut serial, session.pid)
+        .map_err(|e| DeviceError::from_serial(port, e))
+}
+
+// Replace the filter of a running monitor, it applies to the following lines.
+pub fn set_monitor_filter(
+    app: &AppHandle,
+    port: &str,
+    filter: MonitorFilter,
+) -> Result<(), DeviceError> {
+    let filter = LineFilter::new(filter)?;
+    let session = app.state::<MonitorSessions>().get(port)?;
+    *session.rules.filter.lock().unwrap() = filter;
+    Ok(())
+}
+
+// Replace the trigger rules of a running monitor.
+pub fn set_monitor_triggers(
+    app: &AppHandle,
+    port: &str,
+    triggers: Vec<TriggerRule>,
+) -> Result<(), DeviceError> {
+    let triggers = TriggerSet::new(triggers)?;
+    let session = app.state::<MonitorSessions>().get(port)?;
+    *session.rules.triggers.lock().unwrap() = triggers;
+    Ok(())
+}
@@ -0,0 +1,442 @@
+// Turns the raw bytes read by the monitor into whole lines of text. Partial
+// lines and partial UTF-8 sequences are kept until the rest arrives, ANSI color
+// codes are turned into styled spans instead of being printed.
+
+// Lines longer than this are emitted in pieces even without a line break
+const MAX_LINE_LENGTH: usize = 4096;
+
+const ESC: char = '\x1b';
+
+#[derive(Clone, Copy, Default, PartialEq, serde::Serialize)]
+pub struct Style {
+    // ANSI color index, 0-7 normal and 8-15 bright colors
+    pub fg: Option<u8>,
+    pub bg: Option<u8>,
+    pub bold: bool,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct Span {
+    pub text: String,
+    #[serde(flatten)]
+    pub style: Style,
+}
+
+#[derive(Clone)]
+pub struct Line {
+    // Text without escape sequences and line ending
+    pub text: String,
+    pub spans: Vec<Span>,
+    // The line continues in the next emitted line
+    pub partial: bool,
+    // Received from the device, not a message of the monitor itself
+    pub device: bool,
+}
+
+impl Line {
+    pub fn plain(text: &str) -> Self {
+        Line::styled(text, Style::default())
+    }
+
+    pub fn styled(text: &str, style: Style) -> Self {
+        Line {
+            text: text.to_string(),
+            spans: vec![Span {
+                text: text.to_string(),
+                style,
+            }],
+            partial: false,
+            device: false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct LineDecoder {
+    pending: Vec<u8>,
+    // Colors are carried over to the next line like in a terminal
+    style: Style,
+}
+
+impl LineDecoder {
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<Line> {
+        self.pending.extend_from_slice(bytes);
+
+        let mut lines = Vec::new();
+        while let Some(end) = self.pending.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            lines.push(self.decode(&line[..end], false));
+        }
+        if self.pending.len() > MAX_LINE_LENGTH {
+            lines.extend(self.flush_partial());
+        }
+        lines
+    }
+
+    // Emit what was received of the current line, e.g. a prompt waiting for input.
+    // Incomplete UTF-8 and escape sequences at the end stay in the buffer.
+    pub fn flush_partial(&mut self) -> Option<Line> {
+        let end = incomplete_tail(&self.pending);
+        if end == 0 {
+            return None;
+        }
+        let line: Vec<u8> = self.pending.drain(..end).collect();
+        Some(self.decode(&line, true))
+    }
+
+    fn decode(&mut self, bytes: &[u8], partial: bool) -> Line {
+        let text = String::from_utf8_lossy(bytes);
+        let mut spans: Vec<Span> = Vec::new();
+        let mut current = String::new();
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                ESC => {
+                    if chars.peek() != Some(&'[') {
+                        // Not a CSI sequence, drop it together with the next character
+                        chars.next();
+                        continue;
+                    }
+                    chars.next();
+                    let mut params = String::new();
+                    let mut command = None;
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            command = Some(c);
+                            break;
+                        }
+                        params.push(c);
+                    }
+                    if command == Some('m') {
+                        let style = apply_sgr(self.style, &params);
+                        if style != self.style {
+                            push_span(&mut spans, &mut current, self.style);
+                            self.style = style;
+                        }
+                    }
+                }
+                '\t' => current.push(c),
+                '\r' => {}
+                c if c.is_control() => {}
+                c => current.push(c),
+            }
+        }
+        push_span(&mut spans, &mut current, self.style);
+
+        Line {
+            text: spans.iter().map(|span| span.text.as_str()).collect(),
+            spans,
+            partial,
+            device: true,
+        }
+    }
+}
+
+fn push_span(spans: &mut Vec<Span>, text: &mut String, style: Style) {
+    if !text.is_empty() {
+        spans.push(Span {
+            text: std::mem::take(text),
+            style,
+        });
+    }
+}
+
+// Apply a Select Graphic Rendition sequence, e.g. "0;31" from "\x1b[0;31m".
+fn apply_sgr(mut style: Style, params: &str) -> Style {
+    let codes: Vec<u8> = params
+        .split(';')
+        .map(|code| code.parse().unwrap_or(0))
+        .collect();
+    let mut codes = codes.into_iter();
+
+    while let Some(code) = codes.next() {
+        match code {
+            0 => style = Style::default(),
+            1 => style.bold = true,
+            22 => style.bold = false,
+            30..=37 => style.fg = Some(code - 30),
+            39 => style.fg = None,
+            40..=47 => style.bg = Some(code - 40),
+            49 => style.bg = None,
+            90..=97 => style.fg = Some(code - 90 + 8),
+            100..=107 => style.bg = Some(code - 100 + 8),
+            38 | 48 => {
+                // 256 color palette (5;n), only the basic 16 colors are kept.
+                // True color (2;r;g;b) is skipped.
+                let color = match codes.next() {
+                    Some(5) => codes.next().filter(|color| *color < 16),
+                    Some(2) => {
+                        codes.by_ref().take(3).for_each(drop);
+                        None
+                    }
+                    _ => None,
+                };
+                if code == 38 {
+                    style.fg = color;
+                } else {
+                    style.bg = color;
+                }
+            }
+            _ => {}
+        }
+    }
+    style
+}
+
+// Length of the buffer without a trailing incomplete UTF-8 character or
+// unterminated escape sequence.
+fn incomplete_tail(bytes: &[u8]) -> usize {
+    let mut end = bytes.len();
+    let mut offset = 0;
+    while let Err(error) = std::str::from_utf8(&bytes[offset..]) {
+        match error.error_len() {
+            // Invalid bytes in the middle are replaced when decoding
+            Some(len) => offset += error.valid_up_to() + len,
+            // The input ends in the middle of a character
+            None => {
+                end = offset + error.valid_up_to();
+                break;
+            }
+        }
+    }
+
+    if let Some(start) = bytes[..end].iter().rposition(|&byte| byte == 0x1b) {
+        let sequence = &bytes[start + 1..end];
+        let terminated = match sequence.first() {
+            Some(b'[') => sequence[1..]
+                .iter()
+                .any(|byte| (0x40..=0x7e).contains(byte)),
+            Some(_) => true,
+            None => false,
+        };
+        if !terminated {
+            end = start;
+        }
+    }
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Style = Style {
+        fg: Some(1),
+        bg: None,
+        bold: false,
+    };
+    const GREEN: Style = Style {
+        fg: Some(2),
+        bg: None,
+        bold: false,
+    };
+
+    // Feed the input in the given pieces and collect the lines
+    fn feed_all(decoder: &mut LineDecoder, pieces: &[&[u8]]) -> Vec<Line> {
+        pieces
+            .iter()
+            .flat_map(|piece| decoder.feed(piece))
+            .collect()
+    }
+
+    fn texts(lines: &[Line]) -> Vec<&str> {
+        lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    #[test]
+    fn lines_split_at_every_position() {
+        let input = "I (31) boot: ESP-IDF v5.1\r\n\x1b[0;32mI (42) cpu_start: Starting\x1b[0m\r\nCaf\u{e9} \u{1f600}\n";
+        let bytes = input.as_bytes();
+        for split in 0..=bytes.len() {
+            let mut decoder = LineDecoder::default();
+            let lines = feed_all(&mut decoder, &[&bytes[..split], &bytes[split..]]);
+            assert_eq!(
+                texts(&lines),
+                [
+                    "I (31) boot: ESP-IDF v5.1",
+                    "I (42) cpu_start: Starting",
+                    "Caf\u{e9} \u{1f600}"
+                ],
+                "split at {}",
+                split
+            );
+            assert_eq!(lines[1].spans.len(), 1, "split at {}", split);
+            assert!(lines[1].spans[0].style == GREEN, "split at {}", split);
+            assert!(lines.iter().all(|line| !line.partial && line.device));
+        }
+    }
+
+    #[test]
+    fn carriage_return_line_feed_across_reads() {
+        let mut decoder = LineDecoder::default();
+        assert!(decoder.feed(b"abc\r").is_empty());
+        let lines = decoder.feed(b"\ndef\r\n");
+        assert_eq!(texts(&lines), ["abc", "def"]);
+    }
+
+    #[test]
+    fn byte_by_byte() {
+        let input = "\x1b[1;31mE (100) app: failed \u{2717}\x1b[0m\n";
+        let mut decoder = LineDecoder::default();
+        let lines: Vec<Line> = input
+            .as_bytes()
+            .iter()
+            .flat_map(|byte| decoder.feed(&[*byte]))
+            .collect();
+        assert_eq!(texts(&lines), ["E (100) app: failed \u{2717}"]);
+        assert_eq!(lines[0].spans[0].style.fg, Some(1));
+        assert!(lines[0].spans[0].style.bold);
+    }
+
+    #[test]
+    fn style_carried_to_next_line() {
+        let mut decoder = LineDecoder::default();
+        let lines = decoder.feed(b"\x1b[31mred\nstill red\x1b[0m plain\n");
+        assert!(lines[0].spans[0].style == RED);
+        assert_eq!(lines[1].spans.len(), 2);
+        assert!(lines[1].spans[0].style == RED);
+        assert_eq!(lines[1].spans[1].text, " plain");
+        assert!(lines[1].spans[1].style == Style::default());
+    }
+
+    #[test]
+    fn spans_split_at_color_changes() {
+        let mut decoder = LineDecoder::default();
+        let lines = decoder.feed(b"a\x1b[31mb\x1b[31mc\x1b[32md\x1b[0m\n");
+        let spans: Vec<(&str, Style)> = lines[0]
+            .spans
+            .iter()
+            .map(|span| (span.text.as_str(), span.style))
+            .collect();
+        assert!(spans == [("a", Style::default()), ("bc", RED), ("d", GREEN)]);
+        assert_eq!(lines[0].text, "abcd");
+    }
+
+    #[test]
+    fn other_escape_sequences_dropped() {
+        let mut decoder = LineDecoder::default();
+        // Cursor movement, a non CSI escape and control characters
+        let lines = decoder.feed(b"\x1b[2K\x1b[1Aone\x1bc\x07two\tthree\n");
+        assert_eq!(texts(&lines), ["onetwo\tthree"]);
+        assert!(lines[0].spans[0].style == Style::default());
+    }
+
+    #[test]
+    fn invalid_utf8_replaced() {
+        let mut decoder = LineDecoder::default();
+        let lines = decoder.feed(b"a\xffb\n");
+        assert_eq!(texts(&lines), ["a\u{fffd}b"]);
+    }
+
+    #[test]
+    fn flush_partial_keeps_incomplete_sequences() {
+        let mut decoder = LineDecoder::default();
+        assert!(decoder.feed(b"prompt> caf\xc3").is_empty());
+        let partial = decoder.flush_partial().unwrap();
+        assert_eq!(partial.text, "prompt> caf");
+        assert!(partial.partial);
+        // Only the incomplete character is left
+        assert!(decoder.flush_partial().is_none());
+
+        assert!(decoder.feed(b"\xa9 \x1b[0;3").is_empty());
+        assert_eq!(decoder.flush_partial().unwrap().text, "\u{e9} ");
+        assert!(decoder.flush_partial().is_none());
+
+        let lines = decoder.feed(b"1mred\n");
+        assert_eq!(texts(&lines), ["red"]);
+        assert!(lines[0].spans[0].style == RED);
+        assert!(!lines[0].partial);
+    }
+
+    #[test]
+    fn long_lines_emitted_in_pieces() {
+        let mut decoder = LineDecoder::default();
+        let lines = decoder.feed(&[b'x'; MAX_LINE_LENGTH + 1]);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].partial);
+        assert_eq!(lines[0].text.len(), MAX_LINE_LENGTH + 1);
+        let lines = decoder.feed(b"end\n");
+        assert_eq!(texts(&lines), ["end"]);
+    }
+
+    #[test]
+    fn sgr_sequences() {
+        let bright_green_on_red = Style {
+            fg: Some(10),
+            bg: Some(1),
+            bold: false,
+        };
+        let cases = [
+            ("", Style::default(), Style::default()),
+            ("0", RED, Style::default()),
+            ("31", Style::default(), RED),
+            ("0;31", GREEN, RED),
+            ("92;41", Style::default(), bright_green_on_red),
+            ("39", RED, Style::default()),
+            (
+                "101",
+                Style::default(),
+                Style {
+                    bg: Some(9),
+                    ..Style::default()
+                },
+            ),
+            ("1", RED, Style { bold: true, ..RED }),
+            ("22", Style { bold: true, ..RED }, RED),
+            (
+                "38;5;9",
+                Style::default(),
+                Style {
+                    fg: Some(9),
+                    ..Style::default()
+                },
+            ),
+            // Colors beyond the basic 16 are dropped
+            ("38;5;200", RED, Style::default()),
+            (
+                "48;5;2",
+                Style::default(),
+                Style {
+                    bg: Some(2),
+                    ..Style::default()
+                },
+            ),
+            // True color is skipped together with its components
+            (
+                "38;2;255;0;0;1",
+                RED,
+                Style {
+                    fg: None,
+                    bg: None,
+                    bold: true,
+                },
+            ),
+            ("4;7", RED, RED),
+        ];
+        for (params, style, expected) in cases {
+            assert!(apply_sgr(style, params) == expected, "{:?}", params);
+        }
+    }
+
+    #[test]
+    fn incomplete_tails() {
+        let cases: [(&[u8], usize); 11] = [
+            (b"", 0),
+            (b"abc", 3),
+            (b"ab\xc3", 2),
+            (b"ab\xe2\x9c", 2),
+            (b"ab\xe2\x9c\x97", 5),
+            // Invalid bytes are not waited for
+            (b"a\xffb", 3),
+            (b"ab\x1b", 2),
+            (b"ab\x1b[0;3", 2),
+            (b"ab\x1b[0;31m", 9),
+            (b"ab\x1bc", 4),
+            (b"ab\x1b[31mc\xf0\x9f", 8),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(incomplete_tail(bytes), expected, "{:?}", bytes);
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+// ESP-IDF log lines look like "W (1234) wifi: message", the time in the
+// parentheses can also be a wall clock time
+const ESP_LOG_PATTERN: &str = r"^([EWIDV]) \([^)]*\) ([^:]+):";
+
+// Levels of the ESP-IDF logging library, ordered from the most severe.
+#[derive(Clone, Copy, Default, PartialEq, PartialOrd, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Error,
+    Warning,
+    Info,
+    Debug,
+    #[default]
+    Verbose,
+}
+
+impl LogLevel {
+    fn from_letter(letter: &str) -> Option<Self> {
+        match letter {
+            "E" => Some(LogLevel::Error),
+            "W" => Some(LogLevel::Warning),
+            "I" => Some(LogLevel::Info),
+            "D" => Some(LogLevel::Debug),
+            "V" => Some(LogLevel::Verbose),
+            _ => None,
+        }
+    }
+}
+
+// Lines matching the pattern are marked with the color in the UI.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct HighlightRule {
+    pub pattern: String,
+    pub color: String,
+}
+
+// Which lines the monitor forwards to the UI. Lines without a level or tag,
+// e.g. printf output or panics, are only subject to the regex rules.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MonitorFilter {
+    // Most verbose level shown
+    pub level: LogLevel,
+    // Only show these tags, all when empty
+    pub tags: Vec<String>,
+    pub exclude_tags: Vec<String>,
+    // A line has to match one of these when there are any
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub highlights: Vec<HighlightRule>,
+}
+
+// Level and tag of an ESP-IDF log line.
+#[derive(Clone, Default)]
+pub struct LineInfo {
+    pub level: Option<LogLevel>,
+    pub tag: Option<String>,
+    // Color of the first matching highlight rule
+    pub highlight: Option<String>,
+}
+
+pub struct LineFilter {
+    filter: MonitorFilter,
+    log_regex: Regex,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    highlights: Vec<(Regex, String)>,
+}
+
+fn compile(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| format!("Invalid pattern {}: {}", pattern, e))
+}
+
+impl LineFilter {
+    pub fn new(filter: MonitorFilter) -> Result<Self, String> {
+        let include = filter
+            .include
+            .iter()
+            .map(|pattern| compile(pattern))
+            .collect::<Result<_, _>>()?;
+        let exclude = filter
+            .exclude
+            .iter()
+            .map(|pattern| compile(pattern))
+            .collect::<Result<_, _>>()?;
+        let highlights = filter
+            .highlights
+            .iter()
+            .map(|rule| compile(&rule.pattern).map(|regex| (regex, rule.color.clone())))
+            .collect::<Result<_, _>>()?;
+
+        Ok(LineFilter {
+            filter,
+            log_regex: Regex::new(ESP_LOG_PATTERN).unwrap(),
+            include,
+            exclude,
+            highlights,
+        })
+    }
+
+    pub fn info(&self, text: &str) -> LineInfo {
+        let (level, tag) = match self.log_regex.captures(text) {
+            Some(captures) => (
+                LogLevel::from_letter(&captures[1]),
+                Some(captures[2].trim().to_string()),
+            ),
+            None => (None, None),
+        };
+        let highlight = self
+            .highlights
+            .iter()
+            .find(|(regex, _)| regex.is_match(text))
+            .map(|(_, color)| color.clone());
+        LineInfo {
+            level,
+            tag,
+            highlight,
+        }
+    }
+
+    pub fn is_shown(&self, text: &str, info: &LineInfo) -> bool {
+        if info.level.map_or(false, |level| level > self.filter.level) {
+            return false;
+        }
+        if let Some(tag) = &info.tag {
+            if !self.filter.tags.is_empty() && !self.filter.tags.contains(tag) {
+                return false;
+            }
+            if self.filter.exclude_tags.contains(tag) {
+                return false;
+            }
+        }
+        if !self.include.is_empty() && !self.include.iter().any(|regex| regex.is_match(text)) {
+            return false;
+        }
+        !self.exclude.iter().any(|regex| regex.is_match(text))
+    }
+}
+
+impl Default for LineFilter {
+    fn default() -> Self {
+        LineFilter::new(MonitorFilter::default()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor_decoder::LineDecoder;
+
+    fn line_filter(filter: MonitorFilter) -> LineFilter {
+        LineFilter::new(filter).unwrap()
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn classify_log_lines() {
+        let filter = LineFilter::default();
+        let cases = [
+            ("I (123) tag: msg", Some(LogLevel::Info), Some("tag")),
+            (
+                "E (5) wifi: connection failed",
+                Some(LogLevel::Error),
+                Some("wifi"),
+            ),
+            (
+                "W (1234) boot.esp32: PRO CPU",
+                Some(LogLevel::Warning),
+                Some("boot.esp32"),
+            ),
+            (
+                "D (42) my tag: spaces in tag",
+                Some(LogLevel::Debug),
+                Some("my tag"),
+            ),
+            (
+                "V (7) nvs: key: value",
+                Some(LogLevel::Verbose),
+                Some("nvs"),
+            ),
+            // Wall clock timestamps
+            (
+                "I (12:34:56.789) app: started",
+                Some(LogLevel::Info),
+                Some("app"),
+            ),
+            ("I () app: empty time", Some(LogLevel::Info), Some("app")),
+            // No level or tag
+            ("Hello world!", None, None),
+            (
+                "Guru Meditation Error: Core  0 panic'ed (LoadProhibited)",
+                None,
+                None,
+            ),
+            ("Backtrace: 0x400d1234:0x3ffb1230", None, None),
+            ("ets Jun  8 2016 00:22:57", None, None),
+            ("", None, None),
+            // Only a level letter at the start of a line counts
+            ("X (123) tag: msg", None, None),
+            (" I (123) tag: msg", None, None),
+            ("I 123 tag: msg", None, None),
+            ("I (123) no colon", None, None),
+        ];
+        for (text, level, tag) in cases {
+            let info = filter.info(text);
+            assert!(info.level == level, "level of {:?}", text);
+            assert_eq!(info.tag.as_deref(), tag, "tag of {:?}", text);
+        }
+    }
+
+    #[test]
+    fn classify_colored_log_lines() {
+        // The monitor classifies the text after the decoder removed the colors
+        let mut decoder = LineDecoder::default();
+        let lines = decoder.feed(
+            b"\x1b[0;32mI (310) cpu_start: Starting scheduler.\x1b[0m\r\n\
+              \x1b[0;33mW (320) spi_flash: Detected size larger\x1b[0m\r\n\
+              \x1b[0;31mE (330) esp_image: image corrupt\x1b[0m\r\n",
+        );
+        let filter = LineFilter::default();
+        let classified: Vec<(Option<LogLevel>, Option<String>)> = lines
+            .iter()
+            .map(|line| filter.info(&line.text))
+            .map(|info| (info.level, info.tag))
+            .collect();
+        assert!(
+            classified
+                == [
+                    (Some(LogLevel::Info), Some("cpu_start".to_string())),
+                    (Some(LogLevel::Warning), Some("spi_flash".to_string())),
+                    (Some(LogLevel::Error), Some("esp_image".to_string())),
+                ]
+        );
+    }
+
+    #[test]
+    fn filter_by_level() {
+        let filter = line_filter(MonitorFilter {
+            level: LogLevel::Warning,
+            ..MonitorFilter::default()
+        });
+        let cases = [
+            ("E (1) tag: error", true),
+            ("W (1) tag: warning", true),
+            ("I (1) tag: info", false),
+            ("D (1) tag: debug", false),
+            ("V (1) tag: verbose", false),
+            // Lines without a level are always shown
+            ("printf output", true),
+            ("abort() was called at PC 0x40082d5e", true),
+        ];
+        for (text, shown) in cases {
+            assert_eq!(
+                filter.is_shown(text, &filter.info(text)),
+                shown,
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn filter_by_tag() {
+        let filter = line_filter(MonitorFilter {
+            tags: strings(&["wifi", "app"]),
+            exclude_tags: strings(&["app"]),
+            ..MonitorFilter::default()
+        });
+        let cases = [
+            ("I (1) wifi: connected", true),
+            // Excluding wins over including
+            ("I (1) app: started", false),
+            ("I (1) nvs: opened", false),
+            // Tags are compared as a whole
+            ("I (1) wifi_init: rx buffer", false),
+            ("no tag", true),
+        ];
+        for (text, shown) in cases {
+            assert_eq!(
+                filter.is_shown(text, &filter.info(text)),
+                shown,
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn filter_by_regex() {
+        let filter = line_filter(MonitorFilter {
+            include: strings(&["heap", "^Guru"]),
+            exclude: strings(&["(?i)debug"]),
+            ..MonitorFilter::default()
+        });
+        let cases = [
+            ("I (1) app: free heap 1234", true),
+            ("Guru Meditation Error", true),
+            ("I (1) app: Guru", false),
+            ("D (1) app: DEBUG heap", false),
+            ("I (1) app: other", false),
+        ];
+        for (text, shown) in cases {
+            assert_eq!(
+                filter.is_shown(text, &filter.info(text)),
+                shown,
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn highlight_first_matching_rule() {
+        let filter = line_filter(MonitorFilter {
+            highlights: vec![
+                HighlightRule {
+                    pattern: "^E ".to_string(),
+                    color: "red".to_string(),
+                },
+                HighlightRule {
+                    pattern: "wifi".to_string(),
+                    color: "blue".to_string(),
+                },
+            ],
+            ..MonitorFilter::default()
+        });
+        let cases = [
+            ("E (1) wifi: failed", Some("red")),
+            ("I (1) wifi: connected", Some("blue")),
+            ("I (1) app: started", None),
+        ];
+        for (text, color) in cases {
+            assert_eq!(filter.info(text).highlight.as_deref(), color, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn invalid_patterns_rejected() {
+        let filters = [
+            MonitorFilter {
+                include: strings(&["("]),
+                ..MonitorFilter::default()
+            },
+            MonitorFilter {
+                exclude: strings(&["[a-"]),
+                ..MonitorFilter::default()
+            },
+            MonitorFilter {
+                highlights: vec![HighlightRule {
+                    pattern: "*".to_string(),
+                    color: "red".to_string(),
+                }],
+                ..MonitorFilter::default()
+            },
+        ];
+        for filter in filters {
+            let err = LineFilter::new(filter).err().unwrap();
+            assert!(err.starts_with("Invalid pattern"), "{}", err);
+        }
+    }
+}
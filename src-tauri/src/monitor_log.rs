@@ -0,0 +1,490 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tauri::AppHandle;
+
+use crate::device_error::DeviceError;
+use crate::monitor_decoder::Line;
+
+const TEXT_EXTENSION: &str = "log";
+const RAW_EXTENSION: &str = "bin";
+
+#[derive(Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct MonitorLogOptions {
+    // Also capture the received bytes unmodified
+    pub raw: bool,
+    // A new file is started when a file reaches this size
+    pub max_file_size: u64,
+    // Oldest files of the capture are deleted when there are more
+    pub max_files: usize,
+}
+
+impl Default for MonitorLogOptions {
+    fn default() -> Self {
+        MonitorLogOptions {
+            raw: false,
+            max_file_size: 10 * 1024 * 1024,
+            max_files: 10,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct MonitorLogInfo {
+    name: String,
+    path: String,
+    size: u64,
+    // Seconds since the Unix epoch
+    modified: u64,
+    raw: bool,
+}
+
+pub fn monitor_log_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path_resolver()
+        .app_data_dir()
+        .map(|dir| dir.join("monitor_logs"))
+        .ok_or_else(|| "Unable to determine the application data directory".to_string())
+}
+
+fn create_monitor_log_dir(app: &AppHandle) -> Result<PathBuf, DeviceError> {
+    let dir = monitor_log_dir(app)?;
+    fs::create_dir_all(&dir).map_err(|e| DeviceError::file_write(&dir, e))?;
+    Ok(dir)
+}
+
+// Port names like /dev/ttyUSB0 or COM3 turned into a file name with the start time.
+// Milliseconds keep captures and snapshots started in the same second apart.
+fn capture_name(port: &str) -> String {
+    let port_name: String = port
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or(port)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!(
+        "{}_{}",
+        port_name,
+        chrono::Local::now().format("%Y%m%d-%H%M%S-%3f")
+    )
+}
+
+// Host time prefixed to the logged lines.
+pub fn timestamp() -> String {
+    chrono::Local::now()
+        .format("[%Y-%m-%d %H:%M:%S%.3f]")
+        .to_string()
+}
+
+// Write the lines preceding a trigger match next to the monitor logs.
+pub fn save_snapshot<'a>(
+    app: &AppHandle,
+    port: &str,
+    trigger: &str,
+    lines: impl Iterator<Item = &'a String>,
+) -> Result<PathBuf, DeviceError> {
+    let dir = create_monitor_log_dir(app)?;
+    let trigger: String = trigger
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let base_name = format!("{}_snapshot_{}", capture_name(port), trigger);
+
+    let mut content = String::new();
+    for line in lines {
+        content.push_str(line);
+        content.push('\n');
+    }
+
+    // Never overwrite an earlier snapshot, e.g. of a boot loop hitting the trigger repeatedly
+    let mut number = 1;
+    loop {
+        let path = match number {
+            1 => dir.join(format!("{}.{}", base_name, TEXT_EXTENSION)),
+            number => dir.join(format!("{}_{}.{}", base_name, number, TEXT_EXTENSION)),
+        };
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(mut file) => {
+                file.write_all(content.as_bytes())
+                    .map_err(|e| DeviceError::file_write(&path, e))?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => number += 1,
+            Err(e) => return Err(DeviceError::file_write(&path, e)),
+        }
+    }
+}
+
+// Log file split into numbered parts of limited size.
+struct RotatingFile {
+    dir: PathBuf,
+    base_name: String,
+    extension: &'static str,
+    max_size: u64,
+    max_files: usize,
+    part: usize,
+    written: u64,
+    file: BufWriter<File>,
+}
+
+impl RotatingFile {
+    fn create(
+        dir: &Path,
+        base_name: &str,
+        extension: &'static str,
+        options: &MonitorLogOptions,
+    ) -> io::Result<Self> {
+        let path = dir.join(format!("{}.{}", base_name, extension));
+        Ok(RotatingFile {
+            dir: dir.to_path_buf(),
+            base_name: base_name.to_string(),
+            extension,
+            max_size: options.max_file_size.max(1),
+            max_files: options.max_files.max(1),
+            part: 0,
+            written: 0,
+            file: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    fn part_path(&self, part: usize) -> PathBuf {
+        let name = match part {
+            0 => format!("{}.{}", self.base_name, self.extension),
+            part => format!("{}_{:03}.{}", self.base_name, part, self.extension),
+        };
+        self.dir.join(name)
+    }
+
+    fn path(&self) -> PathBuf {
+        self.part_path(self.part)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.part += 1;
+        self.written = 0;
+        self.file = BufWriter::new(File::create(self.path())?);
+
+        if self.part >= self.max_files {
+            let _ = fs::remove_file(self.part_path(self.part - self.max_files));
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.written > 0 && self.written + data.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(data)?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+}
+
+// Writes the monitor output with host timestamps to a file, and optionally the
+// raw bytes to a second one.
+pub struct MonitorLog {
+    text: RotatingFile,
+    raw: Option<RotatingFile>,
+    // The previous line was partial, its continuation gets no timestamp
+    continues_line: bool,
+}
+
+impl MonitorLog {
+    pub fn create(
+        app: &AppHandle,
+        port: &str,
+        options: &MonitorLogOptions,
+    ) -> Result<Self, DeviceError> {
+        let dir = create_monitor_log_dir(app)?;
+        let base_name = capture_name(port);
+
+        let text = RotatingFile::create(&dir, &base_name, TEXT_EXTENSION, options)
+            .map_err(|e| DeviceError::file_write(dir.join(&base_name), e))?;
+        let raw = match options.raw {
+            true => Some(
+                RotatingFile::create(&dir, &base_name, RAW_EXTENSION, options)
+                    .map_err(|e| DeviceError::file_write(dir.join(&base_name), e))?,
+            ),
+            false => None,
+        };
+
+        Ok(MonitorLog {
+            text,
+            raw,
+            continues_line: false,
+        })
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.text.path()
+    }
+
+    pub fn write_raw(&mut self, data: &[u8]) -> Result<(), DeviceError> {
+        match self.raw.as_mut() {
+            Some(raw) => raw
+                .write(data)
+                .map_err(|e| DeviceError::file_write(raw.path(), e)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn write_line(&mut self, line: &Line) -> Result<(), DeviceError> {
+        let mut entry = String::new();
+        if !self.continues_line {
+            entry.push_str(&timestamp());
+            entry.push(' ');
+        }
+        entry.push_str(&line.text);
+        if !line.partial {
+            entry.push('\n');
+        }
+        self.continues_line = line.partial;
+
+        self.text
+            .write(entry.as_bytes())
+            .map_err(|e| DeviceError::file_write(self.text.path(), e))
+    }
+
+    pub fn flush(&mut self) -> Result<(), DeviceError> {
+        for file in std::iter::once(&mut self.text).chain(self.raw.as_mut()) {
+            file.file
+                .flush()
+                .map_err(|e| DeviceError::file_write(file.path(), e))?;
+        }
+        Ok(())
+    }
+}
+
+#[tauri::command]
+pub fn list_monitor_logs(app: AppHandle) -> Result<Vec<MonitorLogInfo>, String> {
+    let dir = monitor_log_dir(&app)?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read {}: {}", dir.display(), e)),
+    };
+
+    let mut logs: Vec<MonitorLogInfo> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let extension = path.extension()?.to_str()?;
+            if extension != TEXT_EXTENSION && extension != RAW_EXTENSION {
+                return None;
+            }
+            let metadata = entry.metadata().ok()?;
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |duration| duration.as_secs());
+            Some(MonitorLogInfo {
+                name: entry.file_name().to_string_lossy().to_string(),
+                path: path.display().to_string(),
+                size: metadata.len(),
+                modified,
+                raw: extension == RAW_EXTENSION,
+            })
+        })
+        .collect();
+    logs.sort_by(|a, b| b.modified.cmp(&a.modified).then(a.name.cmp(&b.name)));
+    Ok(logs)
+}
+
+// Open a capture with the default application of the system.
+#[tauri::command]
+pub fn open_monitor_log(app: AppHandle, name: String) -> Result<(), String> {
+    // Only plain file names, the capture has to be inside the log directory
+    if name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(format!("Invalid monitor log name {}", name));
+    }
+    let path = monitor_log_dir(&app)?.join(&name);
+    if !path.is_file() {
+        return Err(format!("Monitor log {} not found", name));
+    }
+    open::that(&path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Directory for the log files of a test, removed when dropped.
+    struct LogDir(PathBuf);
+
+    impl LogDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("esp-workbench-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            LogDir(dir)
+        }
+
+        // Names and sizes of the files, sorted by name
+        fn files(&self) -> Vec<(String, u64)> {
+            let mut files: Vec<(String, u64)> = fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| entry.unwrap())
+                .map(|entry| {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    (name, entry.metadata().unwrap().len())
+                })
+                .collect();
+            files.sort();
+            files
+        }
+    }
+
+    impl Drop for LogDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn options(max_file_size: u64, max_files: usize) -> MonitorLogOptions {
+        MonitorLogOptions {
+            raw: false,
+            max_file_size,
+            max_files,
+        }
+    }
+
+    fn files(names: &[(&str, u64)]) -> Vec<(String, u64)> {
+        names
+            .iter()
+            .map(|(name, size)| (name.to_string(), *size))
+            .collect()
+    }
+
+    #[test]
+    fn capture_names() {
+        let pattern = regex::Regex::new(r"^(.+)_\d{8}-\d{6}-\d{3}$").unwrap();
+        let cases = [
+            ("/dev/ttyUSB0", "ttyUSB0"),
+            ("COM3", "COM3"),
+            ("/dev/cu.usbserial-1420", "cu_usbserial_1420"),
+            ("\\\\.\\COM12", "COM12"),
+            (
+                "/dev/serial/by-id/usb-Espressif_USB_JTAG",
+                "usb_Espressif_USB_JTAG",
+            ),
+        ];
+        for (port, expected) in cases {
+            let name = capture_name(port);
+            let captures = pattern.captures(&name).unwrap();
+            assert_eq!(&captures[1], expected, "{}", port);
+        }
+    }
+
+    #[test]
+    fn write_within_size_limit() {
+        let dir = LogDir::new("log-within-limit");
+        let mut file = RotatingFile::create(&dir.0, "capture", "log", &options(100, 3)).unwrap();
+        file.write(&[b'a'; 60]).unwrap();
+        file.write(&[b'b'; 40]).unwrap();
+        file.file.flush().unwrap();
+
+        assert_eq!(file.path(), dir.0.join("capture.log"));
+        assert_eq!(dir.files(), files(&[("capture.log", 100)]));
+    }
+
+    #[test]
+    fn rotate_at_size_limit() {
+        let dir = LogDir::new("log-rotate");
+        let mut file = RotatingFile::create(&dir.0, "capture", "log", &options(100, 10)).unwrap();
+        // Writes are not split, a write exceeding the limit starts a new file
+        for size in [60, 40, 1, 99, 50, 51] {
+            file.write(&vec![b'x'; size]).unwrap();
+        }
+        file.file.flush().unwrap();
+
+        assert_eq!(file.path(), dir.0.join("capture_003.log"));
+        assert_eq!(
+            dir.files(),
+            files(&[
+                ("capture.log", 100),
+                ("capture_001.log", 100),
+                ("capture_002.log", 50),
+                ("capture_003.log", 51),
+            ])
+        );
+    }
+
+    #[test]
+    fn oversized_write_gets_own_file() {
+        let dir = LogDir::new("log-oversized");
+        let mut file = RotatingFile::create(&dir.0, "capture", "bin", &options(10, 10)).unwrap();
+        file.write(&[0; 25]).unwrap();
+        file.write(&[0; 5]).unwrap();
+        file.write(&[0; 25]).unwrap();
+        file.file.flush().unwrap();
+
+        assert_eq!(
+            dir.files(),
+            files(&[
+                ("capture.bin", 25),
+                ("capture_001.bin", 5),
+                ("capture_002.bin", 25)
+            ])
+        );
+    }
+
+    #[test]
+    fn delete_oldest_files() {
+        // max files, files left after writing 6 parts
+        let cases = [
+            (
+                3,
+                &["capture_003.log", "capture_004.log", "capture_005.log"][..],
+            ),
+            (1, &["capture_005.log"][..]),
+            // At least one file is kept
+            (0, &["capture_005.log"][..]),
+            (
+                6,
+                &[
+                    "capture.log",
+                    "capture_001.log",
+                    "capture_002.log",
+                    "capture_003.log",
+                    "capture_004.log",
+                    "capture_005.log",
+                ][..],
+            ),
+        ];
+        for (max_files, expected) in cases {
+            let dir = LogDir::new(&format!("log-max-files-{}", max_files));
+            let mut file =
+                RotatingFile::create(&dir.0, "capture", "log", &options(10, max_files)).unwrap();
+            for _ in 0..6 {
+                file.write(&[b'x'; 10]).unwrap();
+            }
+            file.file.flush().unwrap();
+
+            let names: Vec<String> = dir.files().into_iter().map(|(name, _)| name).collect();
+            assert_eq!(names, expected, "{}", max_files);
+        }
+    }
+
+    #[test]
+    fn minimum_file_size() {
+        let dir = LogDir::new("log-zero-size");
+        let mut file = RotatingFile::create(&dir.0, "capture", "log", &options(0, 10)).unwrap();
+        file.write(b"a").unwrap();
+        file.write(b"b").unwrap();
+        file.file.flush().unwrap();
+
+        assert_eq!(
+            dir.files(),
+            files(&[("capture.log", 1), ("capture_001.log", 1)])
+        );
+    }
+}
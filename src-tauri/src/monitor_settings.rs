@@ -0,0 +1,438 @@
+use crate::app_state::CancellationToken;
+use serde::{Deserialize, Serialize};
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+use std::time::{Duration, Instant};
+
+// Rates tried by the baud rate detection, the ROM bootloader of the ESP8266
+// and older ESP32 revisions prints at 74880
+const DETECT_BAUD_RATES: [u32; 9] = [
+    115200, 74880, 9600, 19200, 38400, 57600, 230400, 460800, 921600,
+];
+// How long to listen at each rate
+const DETECT_DURATION: Duration = Duration::from_millis(300);
+// Enough received bytes to judge the rate
+const DETECT_SAMPLE_SIZE: usize = 64;
+// Share of printable bytes above which a rate is taken right away
+const DETECT_PRINTABLE_RATIO: f32 = 0.95;
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MonitorParity {
+    #[default]
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MonitorFlowControl {
+    #[default]
+    None,
+    Software,
+    Hardware,
+}
+
+// Serial line settings of the monitor, 115200 8N1 without flow control by default.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MonitorSettings {
+    pub baud: u32,
+    // Try common baud rates and keep the one giving readable output
+    pub auto_baud: bool,
+    pub data_bits: u8,
+    pub parity: MonitorParity,
+    pub stop_bits: u8,
+    pub flow_control: MonitorFlowControl,
+    // Read timeout, also the longest time a write waits for the reader
+    pub timeout_ms: u64,
+    // Wait for the device to come back when the port disappears
+    pub reconnect: bool,
+}
+
+impl Default for MonitorSettings {
+    fn default() -> Self {
+        MonitorSettings {
+            baud: 115200,
+            auto_baud: false,
+            data_bits: 8,
+            parity: MonitorParity::None,
+            stop_bits: 1,
+            flow_control: MonitorFlowControl::None,
+            timeout_ms: 5,
+            reconnect: true,
+        }
+    }
+}
+
+impl MonitorSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.baud == 0 {
+            return Err("Baud rate must be greater than 0".to_string());
+        }
+        if !(5..=8).contains(&self.data_bits) {
+            return Err(format!("Invalid number of data bits: {}", self.data_bits));
+        }
+        if !(1..=2).contains(&self.stop_bits) {
+            return Err(format!("Invalid number of stop bits: {}", self.stop_bits));
+        }
+        if self.timeout_ms == 0 {
+            return Err("Timeout must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    // Apply the settings to an open port, validate has to be called before.
+    pub fn apply(&self, port: &mut dyn SerialPort) -> serialport::Result<()> {
+        port.set_baud_rate(self.baud)?;
+        port.set_data_bits(match self.data_bits {
+            5 => DataBits::Five,
+            6 => DataBits::Six,
+            7 => DataBits::Seven,
+            _ => DataBits::Eight,
+        })?;
+        port.set_parity(match self.parity {
+            MonitorParity::None => Parity::None,
+            MonitorParity::Odd => Parity::Odd,
+            MonitorParity::Even => Parity::Even,
+        })?;
+        port.set_stop_bits(match self.stop_bits {
+            2 => StopBits::Two,
+            _ => StopBits::One,
+        })?;
+        port.set_flow_control(match self.flow_control {
+            MonitorFlowControl::None => FlowControl::None,
+            MonitorFlowControl::Software => FlowControl::Software,
+            MonitorFlowControl::Hardware => FlowControl::Hardware,
+        })?;
+        port.set_timeout(Duration::from_millis(self.timeout_ms))
+    }
+}
+
+fn printable_ratio(data: &[u8]) -> f32 {
+    let printable = data
+        .iter()
+        .filter(|byte| matches!(byte, b'\t' | b'\n' | b'\r' | 0x1b | 0x20..=0x7e))
+        .count();
+    printable as f32 / data.len() as f32
+}
+
+// Listen at the common baud rates and return the one where the received data
+// looks most like text. None when the device sent nothing at any rate.
+// The port is left at the last tried rate.
+pub fn detect_baud(
+    port: &mut dyn SerialPort,
+    cancel: &CancellationToken,
+) -> serialport::Result<Option<u32>> {
+    let mut best: Option<(u32, f32)> = None;
+    let mut buff = [0; DETECT_SAMPLE_SIZE];
+
+    for baud in DETECT_BAUD_RATES {
+        if cancel.is_cancelled() {
+            break;
+        }
+        port.set_baud_rate(baud)?;
+        port.clear(ClearBuffer::Input)?;
+
+        let mut sample = Vec::new();
+        let start = Instant::now();
+        while start.elapsed() < DETECT_DURATION && sample.len() < DETECT_SAMPLE_SIZE {
+            match port.read(&mut buff) {
+                Ok(count) => sample.extend_from_slice(&buff[..count]),
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if sample.is_empty() {
+            continue;
+        }
+
+        let ratio = printable_ratio(&sample);
+        if ratio >= DETECT_PRINTABLE_RATIO {
+            return Ok(Some(baud));
+        }
+        if best.map_or(true, |(_, best_ratio)| ratio > best_ratio) {
+            best = Some((baud, ratio));
+        }
+    }
+    Ok(best.map(|(baud, _)| baud))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io;
+
+    // Port sending `samples` at the given baud rates and nothing at others,
+    // the settings applied to it are recorded.
+    #[derive(Default)]
+    struct FakePort {
+        samples: HashMap<u32, Vec<u8>>,
+        baud: u32,
+        sent: usize,
+        data_bits: Option<DataBits>,
+        parity: Option<Parity>,
+        stop_bits: Option<StopBits>,
+        flow_control: Option<FlowControl>,
+        timeout: Duration,
+        fail_reads: bool,
+    }
+
+    impl FakePort {
+        fn new(samples: &[(u32, Vec<u8>)]) -> Self {
+            FakePort {
+                samples: samples.iter().cloned().collect(),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl io::Read for FakePort {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.fail_reads {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "unplugged"));
+            }
+            let sample = self
+                .samples
+                .get(&self.baud)
+                .map_or(&[][..], |s| &s[self.sent..]);
+            if sample.is_empty() {
+                std::thread::sleep(Duration::from_millis(1));
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            let count = sample.len().min(buf.len());
+            buf[..count].copy_from_slice(&sample[..count]);
+            self.sent += count;
+            Ok(count)
+        }
+    }
+
+    impl io::Write for FakePort {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SerialPort for FakePort {
+        fn name(&self) -> Option<String> {
+            None
+        }
+        fn baud_rate(&self) -> serialport::Result<u32> {
+            Ok(self.baud)
+        }
+        fn data_bits(&self) -> serialport::Result<DataBits> {
+            Ok(self.data_bits.unwrap_or(DataBits::Eight))
+        }
+        fn flow_control(&self) -> serialport::Result<FlowControl> {
+            Ok(self.flow_control.unwrap_or(FlowControl::None))
+        }
+        fn parity(&self) -> serialport::Result<Parity> {
+            Ok(self.parity.unwrap_or(Parity::None))
+        }
+        fn stop_bits(&self) -> serialport::Result<StopBits> {
+            Ok(self.stop_bits.unwrap_or(StopBits::One))
+        }
+        fn timeout(&self) -> Duration {
+            self.timeout
+        }
+        fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+            self.baud = baud_rate;
+            self.sent = 0;
+            Ok(())
+        }
+        fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+            self.data_bits = Some(data_bits);
+            Ok(())
+        }
+        fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+            self.flow_control = Some(flow_control);
+            Ok(())
+        }
+        fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+            self.parity = Some(parity);
+            Ok(())
+        }
+        fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+            self.stop_bits = Some(stop_bits);
+            Ok(())
+        }
+        fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+            self.timeout = timeout;
+            Ok(())
+        }
+        fn write_request_to_send(&mut self, _level: bool) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn write_data_terminal_ready(&mut self, _level: bool) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+            Ok(false)
+        }
+        fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+            Ok(false)
+        }
+        fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+            Ok(false)
+        }
+        fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+            Ok(false)
+        }
+        fn bytes_to_read(&self) -> serialport::Result<u32> {
+            Ok(0)
+        }
+        fn bytes_to_write(&self) -> serialport::Result<u32> {
+            Ok(0)
+        }
+        fn clear(&self, _buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+            Err(serialport::Error::new(
+                serialport::ErrorKind::Unknown,
+                "not supported",
+            ))
+        }
+        fn set_break(&self) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn clear_break(&self) -> serialport::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Boot output as printed at the right rate
+    fn text() -> Vec<u8> {
+        b"ets Jun  8 2016 00:22:57\r\n\x1b[0;32mI (31) boot: ESP-IDF v5.1.2 2nd stage bootloader\x1b[0m\r\n"
+            .to_vec()
+    }
+
+    // `printable` of every ten bytes are printable
+    fn noise(printable: usize) -> Vec<u8> {
+        (0..DETECT_SAMPLE_SIZE)
+            .map(|i| if i % 10 < printable { b'a' } else { 0xF0 })
+            .collect()
+    }
+
+    #[test]
+    fn printable_ratios() {
+        let cases: [(&[u8], f32); 7] = [
+            (b"hello world\r\n", 1.0),
+            (b"\tI (12) \x1b[0;32mboot\x1b[0m\n", 1.0),
+            (&[0x00, 0xFF, 0x80, 0x7F], 0.0),
+            (b"ab\x00\xFF", 0.5),
+            // Text beyond ASCII does not count
+            ("\u{fc}".as_bytes(), 0.0),
+            (b"~ ", 1.0),
+            (&[0x1F, b'a', b'b', b'c'], 0.75),
+        ];
+        for (data, expected) in cases {
+            assert_eq!(printable_ratio(data), expected, "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn detect_readable_baud() {
+        let cases = [
+            (vec![(115200, text())], Some(115200)),
+            // The ROM bootloader of the ESP8266, garbage at the default rate
+            (vec![(115200, noise(3)), (74880, text())], Some(74880)),
+            (vec![(9600, noise(0)), (921600, text())], Some(921600)),
+            // Nothing readable enough, the most printable rate wins
+            (
+                vec![(115200, noise(5)), (9600, noise(8)), (460800, noise(2))],
+                Some(9600),
+            ),
+            // The first of equally printable rates
+            (vec![(57600, noise(7)), (230400, noise(7))], Some(57600)),
+            (vec![(38400, noise(0))], Some(38400)),
+            // Silent device
+            (vec![], None),
+        ];
+        for (samples, expected) in cases {
+            let rates: Vec<u32> = samples.iter().map(|(baud, _)| *baud).collect();
+            let mut port = FakePort::new(&samples);
+            let baud = detect_baud(&mut port, &CancellationToken::default()).unwrap();
+            assert_eq!(baud, expected, "{:?}", rates);
+        }
+    }
+
+    #[test]
+    fn detect_baud_cancelled() {
+        let mut port = FakePort::new(&[(115200, text())]);
+        let cancel = CancellationToken::default();
+        cancel.cancel();
+        assert_eq!(detect_baud(&mut port, &cancel).unwrap(), None);
+    }
+
+    #[test]
+    fn detect_baud_read_error() {
+        let mut port = FakePort::new(&[(115200, text())]);
+        port.fail_reads = true;
+        assert!(detect_baud(&mut port, &CancellationToken::default()).is_err());
+    }
+
+    #[test]
+    fn validate_settings() {
+        let with = |change: fn(&mut MonitorSettings)| {
+            let mut settings = MonitorSettings::default();
+            change(&mut settings);
+            settings
+        };
+        let cases = [
+            (MonitorSettings::default(), true),
+            (with(|s| s.data_bits = 5), true),
+            (with(|s| s.stop_bits = 2), true),
+            (with(|s| s.baud = 74880), true),
+            (with(|s| s.baud = 0), false),
+            (with(|s| s.data_bits = 4), false),
+            (with(|s| s.data_bits = 9), false),
+            (with(|s| s.stop_bits = 0), false),
+            (with(|s| s.stop_bits = 3), false),
+            (with(|s| s.timeout_ms = 0), false),
+        ];
+        for (index, (settings, valid)) in cases.into_iter().enumerate() {
+            assert_eq!(settings.validate().is_ok(), valid, "{}", index);
+        }
+    }
+
+    #[test]
+    fn apply_settings() {
+        let settings = MonitorSettings {
+            baud: 921600,
+            data_bits: 7,
+            parity: MonitorParity::Even,
+            stop_bits: 2,
+            flow_control: MonitorFlowControl::Hardware,
+            timeout_ms: 20,
+            ..Default::default()
+        };
+        let mut port = FakePort::default();
+        settings.apply(&mut port).unwrap();
+
+        assert_eq!(port.baud, 921600);
+        assert_eq!(port.data_bits, Some(DataBits::Seven));
+        assert_eq!(port.parity, Some(Parity::Even));
+        assert_eq!(port.stop_bits, Some(StopBits::Two));
+        assert_eq!(port.flow_control, Some(FlowControl::Hardware));
+        assert_eq!(port.timeout, Duration::from_millis(20));
+    }
+
+    #[test]
+    fn deserialize_settings_defaults() {
+        let settings: MonitorSettings =
+            serde_json::from_str(r#"{"baud": 74880, "parity": "odd"}"#).unwrap();
+        assert_eq!(settings.baud, 74880);
+        assert!(matches!(settings.parity, MonitorParity::Odd));
+        assert_eq!(settings.data_bits, 8);
+        assert_eq!(settings.stop_bits, 1);
+        assert!(settings.reconnect);
+        assert!(!settings.auto_baud);
+    }
+}
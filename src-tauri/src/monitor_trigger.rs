@@ -0,0 +1,232 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+// Upper limit of the lines kept for snapshots
+const MAX_SNAPSHOT_LINES: usize = 10000;
+
+fn default_snapshot_lines() -> usize {
+    100
+}
+
+// What happens besides the monitor-trigger event when a rule matches.
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerAction {
+    // End monitoring, e.g. when a test passed or the chip crashed
+    Stop,
+    // Save the preceding lines to a file next to the monitor logs
+    Snapshot,
+    // Hard reset the chip
+    Reset,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct TriggerRule {
+    pub name: String,
+    pub pattern: String,
+    #[serde(default)]
+    pub actions: Vec<TriggerAction>,
+    // Lines saved by the snapshot action, including the matching line
+    #[serde(default = "default_snapshot_lines")]
+    pub snapshot_lines: usize,
+    // Fire only on the first match
+    #[serde(default)]
+    pub once: bool,
+}
+
+struct Trigger {
+    rule: TriggerRule,
+    regex: Regex,
+    fired: bool,
+}
+
+// Rules checked against every line received by the monitor.
+#[derive(Default)]
+pub struct TriggerSet {
+    triggers: Vec<Trigger>,
+}
+
+impl TriggerSet {
+    pub fn new(rules: Vec<TriggerRule>) -> Result<Self, String> {
+        let triggers = rules
+            .into_iter()
+            .map(|rule| {
+                let regex = Regex::new(&rule.pattern)
+                    .map_err(|e| format!("Invalid pattern of trigger {}: {}", rule.name, e))?;
+                Ok(Trigger {
+                    rule,
+                    regex,
+                    fired: false,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(TriggerSet { triggers })
+    }
+
+    // Number of lines the monitor has to remember for the snapshots.
+    pub fn history_size(&self) -> usize {
+        self.triggers
+            .iter()
+            .filter(|trigger| trigger.rule.actions.contains(&TriggerAction::Snapshot))
+            .map(|trigger| trigger.rule.snapshot_lines.min(MAX_SNAPSHOT_LINES))
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Rules matching the line, rules firing once are disabled afterwards.
+    pub fn matches(&mut self, text: &str) -> Vec<TriggerRule> {
+        self.triggers
+            .iter_mut()
+            .filter(|trigger| !(trigger.rule.once && trigger.fired))
+            .filter(|trigger| trigger.regex.is_match(text))
+            .map(|trigger| {
+                trigger.fired = true;
+                trigger.rule.clone()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(name: &str, pattern: &str, actions: &[TriggerAction]) -> TriggerRule {
+        TriggerRule {
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+            actions: actions.to_vec(),
+            snapshot_lines: default_snapshot_lines(),
+            once: false,
+        }
+    }
+
+    fn names(rules: Vec<TriggerRule>) -> Vec<String> {
+        rules.into_iter().map(|rule| rule.name).collect()
+    }
+
+    #[test]
+    fn match_lines() {
+        let mut triggers = TriggerSet::new(vec![
+            rule(
+                "panic",
+                r"Guru Meditation Error",
+                &[TriggerAction::Snapshot],
+            ),
+            rule("passed", r"^\d+ Tests 0 Failures", &[TriggerAction::Stop]),
+            rule("error", r"^E \(\d+\)", &[]),
+        ])
+        .unwrap();
+
+        let cases = [
+            (
+                "Guru Meditation Error: Core  0 panic'ed (LoadProhibited)",
+                vec!["panic"],
+            ),
+            ("12 Tests 0 Failures 0 Ignored", vec!["passed"]),
+            (
+                "E (123) wifi: Guru Meditation Error",
+                vec!["panic", "error"],
+            ),
+            // Anchored patterns only match at the start
+            ("Summary: 12 Tests 0 Failures", vec![]),
+            ("I (123) main: running", vec![]),
+            ("", vec![]),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(names(triggers.matches(line)), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn match_once() {
+        let mut once = rule("boot", "rst:0x1", &[]);
+        once.once = true;
+        let mut triggers = TriggerSet::new(vec![once, rule("reset", "rst:", &[])]).unwrap();
+
+        assert_eq!(
+            names(triggers.matches("rst:0x1 (POWERON)")),
+            ["boot", "reset"]
+        );
+        assert_eq!(names(triggers.matches("rst:0x1 (POWERON)")), ["reset"]);
+        assert_eq!(names(triggers.matches("rst:0xc (SW_CPU)")), ["reset"]);
+        assert_eq!(names(triggers.matches("rst:0x1 (POWERON)")), ["reset"]);
+    }
+
+    #[test]
+    fn once_only_counts_matches() {
+        let mut once = rule("ready", "ready", &[]);
+        once.once = true;
+        let mut triggers = TriggerSet::new(vec![once]).unwrap();
+
+        assert!(triggers.matches("booting").is_empty());
+        assert_eq!(names(triggers.matches("ready")), ["ready"]);
+        assert!(triggers.matches("ready").is_empty());
+    }
+
+    #[test]
+    fn history_sizes() {
+        let snapshot = |lines: usize| {
+            let mut rule = rule("snapshot", "x", &[TriggerAction::Snapshot]);
+            rule.snapshot_lines = lines;
+            rule
+        };
+        let no_snapshot = |lines: usize| {
+            let mut rule = rule("stop", "x", &[TriggerAction::Stop, TriggerAction::Reset]);
+            rule.snapshot_lines = lines;
+            rule
+        };
+
+        let cases = [
+            (vec![], 0),
+            (vec![snapshot(50)], 50),
+            (vec![snapshot(50), snapshot(200)], 200),
+            // Only rules saving snapshots need a history
+            (vec![no_snapshot(500)], 0),
+            (vec![snapshot(50), no_snapshot(500)], 50),
+            // Capped to keep the memory of the monitor bounded
+            (vec![snapshot(MAX_SNAPSHOT_LINES + 1)], MAX_SNAPSHOT_LINES),
+            (vec![snapshot(usize::MAX), snapshot(10)], MAX_SNAPSHOT_LINES),
+            (vec![snapshot(0)], 0),
+        ];
+        for (rules, expected) in cases {
+            let lines: Vec<usize> = rules.iter().map(|rule| rule.snapshot_lines).collect();
+            let triggers = TriggerSet::new(rules).unwrap();
+            assert_eq!(triggers.history_size(), expected, "{:?}", lines);
+        }
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        let cases = ["(unclosed", "[a-", "*start", r"\p{Unknown}"];
+        for pattern in cases {
+            let rules = vec![rule("valid", "ok", &[]), rule("broken", pattern, &[])];
+            match TriggerSet::new(rules) {
+                Ok(_) => panic!("{} accepted", pattern),
+                Err(error) => {
+                    assert!(
+                        error.starts_with("Invalid pattern of trigger broken"),
+                        "{}",
+                        error
+                    )
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn deserialize_rule_defaults() {
+        let rule: TriggerRule =
+            serde_json::from_str(r#"{"name": "panic", "pattern": "abort\\(\\)"}"#).unwrap();
+        assert!(rule.actions.is_empty());
+        assert_eq!(rule.snapshot_lines, 100);
+        assert!(!rule.once);
+
+        let rule: TriggerRule = serde_json::from_str(
+            r#"{"name": "panic", "pattern": "x", "actions": ["snapshot", "reset"], "once": true}"#,
+        )
+        .unwrap();
+        assert!(rule.actions == [TriggerAction::Snapshot, TriggerAction::Reset]);
+        assert!(rule.once);
+    }
+}
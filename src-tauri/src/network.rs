@@ -0,0 +1,182 @@
+use crate::settings::{load_settings, save_settings};
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+
+const NETWORK_SETTINGS_FILE: &str = "network_settings.json";
+const USER_AGENT: &str = "esp-workbench";
+const GITHUB_URL: &str = "https://github.com";
+const ESPRESSIF_URL: &str = "https://dl.espressif.com";
+// Espressif mirrors the release assets of its GitHub repositories here
+const ESPRESSIF_GITHUB_ASSETS_URL: &str = "https://dl.espressif.com/github_assets";
+const PROXY_VARIABLES: [&str; 4] = ["HTTP_PROXY", "HTTPS_PROXY", "http_proxy", "https_proxy"];
+// Read by Python (pip, idf_tools.py), curl and rustup
+const CA_BUNDLE_VARIABLES: [&str; 3] = ["SSL_CERT_FILE", "REQUESTS_CA_BUNDLE", "CURL_CA_BUNDLE"];
+
+// Where release assets and the ESP-IDF version list are downloaded from.
+// github: assets from GitHub, the version list from dl.espressif.com.
+// espressif: everything from the Espressif CDN, faster e.g. in China.
+// custom: everything from `custom_mirror`, laid out like the Espressif CDN.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mirror {
+    #[default]
+    Github,
+    Espressif,
+    Custom,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    pub mirror: Mirror,
+    // Base URL of the custom mirror
+    pub custom_mirror: Option<String>,
+    // HTTP(S) proxy for all requests, the system proxy variables apply otherwise
+    pub proxy: Option<String>,
+    // PEM file with certificates trusted in addition to the system ones.
+    // Installers run as child processes trust only this file, so it has to
+    // contain the public certificates as well.
+    pub ca_bundle: Option<String>,
+    // Replaces sh.rustup.rs or win.rustup.rs as the source of rustup-init
+    pub rustup_init_url: Option<String>,
+    // Replaces static.rust-lang.org as the source of the Rust toolchains
+    pub rustup_dist_server: Option<String>,
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+// The PEM blocks of a bundle, reqwest parses one certificate at a time.
+fn pem_certificates(pem: &str) -> Vec<&str> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+    let mut certificates = Vec::new();
+    let mut rest = pem;
+    while let Some(start) = rest.find(BEGIN) {
+        let Some(end) = rest[start..].find(END) else {
+            break;
+        };
+        let end = start + end + END.len();
+        certificates.push(&rest[start..end]);
+        rest = &rest[end..];
+    }
+    certificates
+}
+
+impl NetworkSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.mirror == Mirror::Custom && non_empty(&self.custom_mirror).is_none() {
+            return Err("The custom mirror needs a base URL".to_string());
+        }
+        self.client().map(|_| ())
+    }
+
+    fn custom_base(&self) -> &str {
+        non_empty(&self.custom_mirror)
+            .unwrap_or(ESPRESSIF_URL)
+            .trim_end_matches('/')
+    }
+
+    // URL of a GitHub release asset, `path` starts with the repository,
+    // e.g. "espressif/esp-idf/releases/download/v5.1/esp-idf-v5.1.zip".
+    pub fn github_asset_url(&self, path: &str) -> String {
+        match self.mirror {
+            Mirror::Github => format!("{}/{}", GITHUB_URL, path),
+            Mirror::Espressif => format!("{}/{}", ESPRESSIF_GITHUB_ASSETS_URL, path),
+            Mirror::Custom => format!("{}/github_assets/{}", self.custom_base(), path),
+        }
+    }
+
+    // URL of a file on dl.espressif.com, e.g. "dl/esp-idf/idf_versions.js".
+    pub fn espressif_url(&self, path: &str) -> String {
+        match self.mirror {
+            Mirror::Github | Mirror::Espressif => format!("{}/{}", ESPRESSIF_URL, path),
+            Mirror::Custom => format!("{}/{}", self.custom_base(), path),
+        }
+    }
+
+    pub fn rustup_init_url<'a>(&'a self, default: &'a str) -> &'a str {
+        non_empty(&self.rustup_init_url).unwrap_or(default)
+    }
+
+    // Environment of the installers run as child processes, rustup-init, espup
+    // and the ESP-IDF install script download on their own.
+    pub fn command_env(&self) -> Vec<(String, String)> {
+        let mut env = Vec::new();
+        let mut set = |names: &[&str], value: &str| {
+            for name in names {
+                env.push((name.to_string(), value.to_string()));
+            }
+        };
+        if let Some(proxy) = non_empty(&self.proxy) {
+            set(&PROXY_VARIABLES, proxy);
+        }
+        if let Some(ca_bundle) = non_empty(&self.ca_bundle) {
+            set(&CA_BUNDLE_VARIABLES, ca_bundle);
+        }
+        // idf_tools.py takes the host and path of the mirror, always using HTTPS
+        let github_assets = match self.mirror {
+            Mirror::Github => None,
+            Mirror::Espressif => Some(ESPRESSIF_GITHUB_ASSETS_URL.to_string()),
+            Mirror::Custom => Some(format!("{}/github_assets", self.custom_base())),
+        };
+        if let Some(url) = github_assets {
+            let host_path = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+            set(&["IDF_GITHUB_ASSETS"], host_path);
+        }
+        if let Some(server) = non_empty(&self.rustup_dist_server) {
+            let server = server.trim_end_matches('/');
+            set(&["RUSTUP_DIST_SERVER"], server);
+            set(&["RUSTUP_UPDATE_ROOT"], &format!("{}/rustup", server));
+        }
+        env
+    }
+
+    // Every HTTP request of the application goes through a client built here.
+    pub fn client(&self) -> Result<reqwest::Client, String> {
+        let mut builder = reqwest::Client::builder().user_agent(USER_AGENT);
+        if let Some(proxy) = non_empty(&self.proxy) {
+            let proxy = reqwest::Proxy::all(proxy)
+                .map_err(|err| format!("Invalid proxy {}: {}", proxy, err))?;
+            builder = builder.proxy(proxy);
+        }
+        if let Some(path) = non_empty(&self.ca_bundle) {
+            let pem =
+                std::fs::read(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+            let pem = String::from_utf8_lossy(&pem);
+            let certificates = pem_certificates(&pem);
+            if certificates.is_empty() {
+                return Err(format!("No certificates found in {}", path));
+            }
+            for certificate in certificates {
+                let certificate = reqwest::Certificate::from_pem(certificate.as_bytes())
+                    .map_err(|err| format!("Invalid certificate in {}: {}", path, err))?;
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        builder
+            .build()
+            .map_err(|err| format!("Failed to create reqwest client: {}", err))
+    }
+}
+
+pub fn load_network_settings(app: &AppHandle) -> NetworkSettings {
+    load_settings(app, NETWORK_SETTINGS_FILE)
+}
+
+// Command to get the mirror and proxy configuration
+#[tauri::command]
+pub fn get_network_settings(app: AppHandle) -> NetworkSettings {
+    load_network_settings(&app)
+}
+
+// Command to change the mirror and proxy configuration
+#[tauri::command]
+pub fn set_network_settings(app: AppHandle, settings: NetworkSettings) -> Result<(), String> {
+    settings.validate()?;
+    save_settings(&app, NETWORK_SETTINGS_FILE, &settings)
+}
@@ -0,0 +1,924 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tauri::{AppHandle, Manager, Window};
+
+use crate::app_state::{finish_job, start_job, CancellationToken, JobKind};
+use crate::download::file_sha256;
+use crate::esp_idf::run_install_script;
+use crate::external_command::run_external_command_with_progress;
+use crate::zip_archiver::unzip;
+
+// Lists the content of the bundle, placed in the bundle root.
+const MANIFEST_FILE: &str = "manifest.json";
+// idf_tools.py takes tool archives found here instead of downloading them
+const TOOLS_DIST_DIR: &str = "dist";
+// pip installs the Python packages of ESP-IDF from here
+const WHEELS_DIR: &str = "python";
+// Name under which espup installs the Xtensa Rust toolchain
+const ESP_TOOLCHAIN: &str = "esp";
+// Targets espup adds to the nightly toolchain for the RISC-V chips
+const RISCV_TARGETS: [&str; 3] = [
+    "riscv32imc-unknown-none-elf",
+    "riscv32imac-unknown-none-elf",
+    "riscv32imafc-unknown-none-elf",
+];
+
+#[cfg(unix)]
+const RUSTUP_NAME: &str = "rustup";
+#[cfg(windows)]
+const RUSTUP_NAME: &str = "rustup.exe";
+
+#[cfg(unix)]
+const EXPORT_FILE: &str = "export-esp.sh";
+#[cfg(windows)]
+const EXPORT_FILE: &str = "export-esp.ps1";
+// libclang is in lib on Linux and macOS, next to clang in bin on Windows
+#[cfg(unix)]
+const LIBCLANG_DIR: &str = "lib";
+#[cfg(windows)]
+const LIBCLANG_DIR: &str = "bin";
+
+// File of the bundle, `path` is relative to the bundle root.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct BundleFile {
+    pub path: String,
+    pub sha256: String,
+}
+
+// Directory of the bundle laid out like a download server, with its files
+// listed for validation.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct BundleMirror {
+    pub path: String,
+    pub files: Vec<BundleFile>,
+}
+
+impl BundleMirror {
+    // Check that an archive of each component is listed, e.g. rust-src-nightly
+    // for dist/2024-01-25/rust-src-nightly.tar.xz.
+    fn check_archives(&self, components: &[String]) -> Result<(), String> {
+        for component in components {
+            let prefix = format!("{}.tar.", component);
+            let listed = self.files.iter().any(|file| {
+                file.path
+                    .rsplit(['/', '\\'])
+                    .next()
+                    .map_or(false, |name| name.starts_with(&prefix))
+            });
+            if !listed {
+                return Err(format!(
+                    "The Rust distribution of the offline bundle contains no {}",
+                    component
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Components of the nightly toolchain installed for the RISC-V chips, with the
+// compiler of `host` when it is given.
+fn nightly_components(host: Option<&str>) -> Vec<String> {
+    let mut components = vec!["rust-src-nightly".to_string()];
+    if let Some(host) = host {
+        for name in ["rustc", "cargo", "rust-std"] {
+            components.push(format!("{}-nightly-{}", name, host));
+        }
+    }
+    for target in RISCV_TARGETS {
+        components.push(format!("rust-std-nightly-{}", target));
+    }
+    components
+}
+
+// Content of manifest.json. Bundles are made for one platform, e.g. rustup is
+// rustup-init.exe in a bundle for Windows.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BundleManifest {
+    pub esp_idf_version: Option<String>,
+    // Release zip of ESP-IDF as published on GitHub
+    pub esp_idf: Option<BundleFile>,
+    // Archives of the tools installed by the ESP-IDF install script
+    pub tools: Vec<BundleFile>,
+    // Wheels of the packages of the ESP-IDF Python environment
+    pub python_wheels: Vec<BundleFile>,
+    // espidf.constraints.v*.txt of the ESP-IDF version, downloaded by idf_tools.py otherwise
+    pub constraints: Option<BundleFile>,
+    pub rustup: Option<BundleFile>,
+    pub espup: Option<BundleFile>,
+    // Copy of static.rust-lang.org with the stable and nightly channel manifests
+    // and the archives of the host, rust-src and the rust-std of the RISC-V
+    // targets, rustup installs the toolchains from it
+    pub rust_dist: Option<BundleMirror>,
+    // Zip of the "esp" toolchain directory as installed by `espup install`,
+    // including the Xtensa GCC and LLVM. espup only installs from the network.
+    pub esp_toolchain: Option<BundleFile>,
+}
+
+impl BundleManifest {
+    fn files(&self) -> impl Iterator<Item = &BundleFile> {
+        self.esp_idf
+            .iter()
+            .chain(self.tools.iter())
+            .chain(self.python_wheels.iter())
+            .chain(self.constraints.iter())
+            .chain(self.rustup.iter())
+            .chain(self.espup.iter())
+            .chain(self.rust_dist.iter().flat_map(|mirror| mirror.files.iter()))
+            .chain(self.esp_toolchain.iter())
+    }
+}
+
+// Each extracted archive gets its own directory, a bundle kept by
+// ValidatedBundles must not be overwritten by the next one.
+static NEXT_EXTRACTION: AtomicUsize = AtomicUsize::new(0);
+
+// Offline bundle given either as a directory or as a zip archive with the
+// bundle directory at the top level. Archives are extracted to a temporary
+// directory which is removed again when the bundle is dropped.
+pub struct OfflineBundle {
+    root: PathBuf,
+    pub manifest: BundleManifest,
+    extracted: bool,
+}
+
+impl OfflineBundle {
+    pub fn open(window: &Window, cancel: &CancellationToken, path: &str) -> Result<Self, String> {
+        let bundle_path = Path::new(path);
+        let (root, extracted) = if bundle_path.is_dir() {
+            (bundle_path.to_path_buf(), false)
+        } else if bundle_path.is_file() {
+            let name = bundle_path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy();
+            let root = std::env::temp_dir().join(format!(
+                "esp-workbench-{}-{}-{}",
+                name,
+                std::process::id(),
+                NEXT_EXTRACTION.fetch_add(1, Ordering::SeqCst)
+            ));
+            info!("Extracting offline bundle to {}", root.display());
+            let _ = std::fs::remove_dir_all(&root);
+            unzip(
+                window.clone(),
+                cancel.clone(),
+                path.to_string(),
+                root.display().to_string(),
+            )
+            .map_err(|e| format!("Failed to extract {}: {}", path, e))?;
+            (root, true)
+        } else {
+            return Err(format!("Offline bundle {} not found", path));
+        };
+
+        // Constructed before reading the manifest, so an extracted bundle is removed on errors
+        let mut bundle = OfflineBundle {
+            root,
+            manifest: BundleManifest::default(),
+            extracted,
+        };
+        if cancel.is_cancelled() {
+            return Err("Installation aborted".to_string());
+        }
+        let manifest_path = bundle.root.join(MANIFEST_FILE);
+        let content = std::fs::read_to_string(&manifest_path)
+            .map_err(|e| format!("Failed to read {}: {}", manifest_path.display(), e))?;
+        bundle.manifest = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid {}: {}", manifest_path.display(), e))?;
+        Ok(bundle)
+    }
+
+    pub fn file_path(&self, file: &BundleFile) -> PathBuf {
+        self.root.join(&file.path)
+    }
+
+    // Environment pointing rustup at the Rust distribution of the bundle.
+    pub fn rustup_env(&self) -> Result<Vec<(String, String)>, String> {
+        let mirror = self
+            .manifest
+            .rust_dist
+            .as_ref()
+            .ok_or("The offline bundle contains no Rust distribution")?;
+        check_path(&mirror.path)?;
+        let url = file_url(&self.root.join(&mirror.path).display().to_string());
+        Ok(vec![
+            ("RUSTUP_DIST_SERVER".to_string(), url.clone()),
+            ("RUSTUP_UPDATE_ROOT".to_string(), format!("{}/rustup", url)),
+        ])
+    }
+
+    // Check that the bundle has everything to install the Rust toolchains for `host`.
+    pub fn check_rust_toolchains(&self, host: Option<&str>) -> Result<(), String> {
+        if self.manifest.esp_toolchain.is_none() {
+            return Err("The offline bundle contains no Xtensa Rust toolchain".to_string());
+        }
+        self.manifest
+            .rust_dist
+            .as_ref()
+            .ok_or("The offline bundle contains no Rust distribution")?
+            .check_archives(&nightly_components(host))
+    }
+
+    // Check that all files listed in the manifest are present and intact.
+    pub fn validate(&self, cancel: &CancellationToken) -> Result<(), String> {
+        for file in self.manifest.files() {
+            if cancel.is_cancelled() {
+                return Err("Installation aborted".to_string());
+            }
+            check_path(&file.path)?;
+            let path = self.file_path(file);
+            let actual = file_sha256(&path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            if !actual.eq_ignore_ascii_case(&file.sha256) {
+                return Err(format!(
+                    "Checksum mismatch for {}: expected {}, got {}",
+                    file.path, file.sha256, actual
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Copy a file of the bundle to its installation path.
+    pub fn install_file(&self, file: &BundleFile, dest_path: &Path) -> Result<(), String> {
+        if let Some(parent) = dest_path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        std::fs::copy(self.file_path(file), dest_path)
+            .map(|_| ())
+            .map_err(|e| {
+                format!(
+                    "Failed to copy {} to {}: {}",
+                    file.path,
+                    dest_path.display(),
+                    e
+                )
+            })
+    }
+}
+
+impl Drop for OfflineBundle {
+    fn drop(&mut self) {
+        if self.extracted {
+            let _ = std::fs::remove_dir_all(&self.root);
+        }
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// Bundles checked by validate_offline_bundle, kept for the installation so an
+// archive is not extracted and hashed a second time. A bundle is only reused
+// while its modification time is unchanged.
+#[derive(Default)]
+pub struct ValidatedBundles(Mutex<HashMap<String, (SystemTime, OfflineBundle)>>);
+
+impl ValidatedBundles {
+    fn insert(&self, path: &str, bundle: OfflineBundle) {
+        if let Some(modified) = modified(path) {
+            let mut bundles = self.0.lock().unwrap();
+            bundles.insert(path.to_string(), (modified, bundle));
+        }
+    }
+
+    fn take(&self, path: &str) -> Option<OfflineBundle> {
+        let (validated, bundle) = self.0.lock().unwrap().remove(path)?;
+        match modified(path) == Some(validated) {
+            true => Some(bundle),
+            false => None,
+        }
+    }
+}
+
+// The bundle validated before, or the bundle opened and validated now.
+pub fn open_validated(
+    window: &Window,
+    cancel: &CancellationToken,
+    path: &str,
+) -> Result<OfflineBundle, String> {
+    if let Some(bundle) = window.state::<ValidatedBundles>().take(path) {
+        info!("Using the offline bundle validated before");
+        return Ok(bundle);
+    }
+    let bundle = OfflineBundle::open(window, cancel, path)?;
+    bundle.validate(cancel)?;
+    Ok(bundle)
+}
+
+fn file_url(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    match path.starts_with('/') {
+        true => format!("file://{}", path),
+        // Windows paths start with the drive letter
+        false => format!("file:///{}", path),
+    }
+}
+
+// Paths must stay inside the bundle
+fn check_path(path: &str) -> Result<(), String> {
+    if Path::new(path).is_absolute() || path.split(['/', '\\']).any(|c| c == "..") {
+        return Err(format!("Invalid path {} in the manifest", path));
+    }
+    Ok(())
+}
+
+fn file_name(file: &BundleFile) -> Result<&std::ffi::OsStr, String> {
+    Path::new(&file.path)
+        .file_name()
+        .ok_or_else(|| format!("Invalid path {} in the manifest", file.path))
+}
+
+// Install ESP-IDF from the bundle: the tool archives go to the dist directory
+// of the tools path, ESP-IDF is extracted to `esp_idf_path` and the install
+// script of ESP-IDF sets the tools up from the archives and the Python
+// environment from the wheels.
+pub async fn install_esp_idf_from_bundle(
+    window: Window,
+    cancel: CancellationToken,
+    bundle_path: String,
+    tools_path: String,
+    esp_idf_path: String,
+) -> Result<(), String> {
+    let bundle = open_validated(&window, &cancel, &bundle_path)?;
+    let esp_idf = bundle
+        .manifest
+        .esp_idf
+        .as_ref()
+        .ok_or("The offline bundle contains no ESP-IDF")?;
+
+    let dist_dir = Path::new(&tools_path).join(TOOLS_DIST_DIR);
+    for tool in &bundle.manifest.tools {
+        bundle.install_file(tool, &dist_dir.join(file_name(tool)?))?;
+    }
+    let wheels_dir = dist_dir.join(WHEELS_DIR);
+    for wheel in &bundle.manifest.python_wheels {
+        bundle.install_file(wheel, &wheels_dir.join(file_name(wheel)?))?;
+    }
+    if let Some(constraints) = &bundle.manifest.constraints {
+        bundle.install_file(
+            constraints,
+            &Path::new(&tools_path).join(file_name(constraints)?),
+        )?;
+    }
+
+    info!("Extracting ESP-IDF to {}", esp_idf_path);
+    unzip(
+        window.clone(),
+        cancel.clone(),
+        bundle.file_path(esp_idf).display().to_string(),
+        esp_idf_path.clone(),
+    )
+    .map_err(|e| format!("Failed to extract ESP-IDF: {}", e))?;
+    if cancel.is_cancelled() {
+        return Err("Installation aborted".to_string());
+    }
+
+    let mut envs = vec![("IDF_TOOLS_PATH".to_string(), tools_path.clone())];
+    if !bundle.manifest.python_wheels.is_empty() {
+        envs.push(("PIP_NO_INDEX".to_string(), "1".to_string()));
+        envs.push((
+            "PIP_FIND_LINKS".to_string(),
+            wheels_dir.display().to_string(),
+        ));
+    }
+    run_install_script(window, cancel, esp_idf_path, envs)
+        .await
+        .map(|_| ())
+        .map_err(|_| "ESP-IDF install script failed".to_string())
+}
+
+// Directory given by `variable` like rustup does, or `default` in the home directory.
+fn rust_dir(variable: &str, default: &str) -> Result<PathBuf, String> {
+    match std::env::var_os(variable) {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => dirs::home_dir()
+            .map(|home| home.join(default))
+            .ok_or_else(|| "Failed to get home directory".to_string()),
+    }
+}
+
+// The toolchains of espup are installed in a directory named after the version,
+// e.g. xtensa-esp-elf/esp-13.2.0_20230928/xtensa-esp-elf/bin.
+fn versioned_dir(dir: &Path, inner: &[&str]) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| {
+            inner
+                .iter()
+                .fold(entry.path(), |path, name| path.join(name))
+        })
+        .find(|path| path.is_dir())
+}
+
+// Content of the export file of espup, setting up PATH and LIBCLANG_PATH for
+// the Xtensa toolchain extracted to `toolchain_dir`.
+fn export_file_content(toolchain_dir: &Path) -> Result<String, String> {
+    let libclang = versioned_dir(
+        &toolchain_dir.join("xtensa-esp32-elf-clang"),
+        &["esp-clang", LIBCLANG_DIR],
+    )
+    .ok_or("The Xtensa toolchain of the offline bundle contains no LLVM")?;
+    let gcc: Vec<PathBuf> = ["xtensa-esp-elf", "riscv32-esp-elf"]
+        .iter()
+        .filter_map(|name| versioned_dir(&toolchain_dir.join(name), &[name, "bin"]))
+        .collect();
+
+    #[cfg(unix)]
+    let content = {
+        let mut content = format!("export LIBCLANG_PATH=\"{}\"\n", libclang.display());
+        for dir in &gcc {
+            content.push_str(&format!("export PATH=\"{}:$PATH\"\n", dir.display()));
+        }
+        content
+    };
+    #[cfg(windows)]
+    let content = {
+        let mut content = format!("$Env:LIBCLANG_PATH = \"{}\"\n", libclang.display());
+        for dir in &gcc {
+            content.push_str(&format!("$Env:PATH = \"{};\" + $Env:PATH\n", dir.display()));
+        }
+        content
+    };
+    Ok(content)
+}
+
+fn write_export_file(toolchain_dir: &Path) -> Result<PathBuf, String> {
+    let content = export_file_content(toolchain_dir)?;
+    let path = dirs::home_dir()
+        .ok_or("Failed to get home directory")?
+        .join(EXPORT_FILE);
+    std::fs::write(&path, content)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+// Set up what `espup install` downloads otherwise: the nightly toolchain with
+// the RISC-V targets comes from the Rust distribution of the bundle, the
+// Xtensa toolchain is extracted to the toolchains of rustup. `selected_variant`
+// is the host of the toolchain, e.g. x86_64-pc-windows-gnu.
+pub async fn install_rust_toolchains_from_bundle(
+    window: Window,
+    cancel: CancellationToken,
+    bundle: &OfflineBundle,
+    selected_variant: Option<&String>,
+) -> Result<(), String> {
+    let esp_toolchain = bundle
+        .manifest
+        .esp_toolchain
+        .as_ref()
+        .ok_or("The offline bundle contains no Xtensa Rust toolchain")?;
+    let envs = bundle.rustup_env()?;
+
+    let rustup_path = rust_dir("CARGO_HOME", ".cargo")?
+        .join("bin")
+        .join(RUSTUP_NAME);
+    let toolchain = match selected_variant {
+        Some(variant) => format!("nightly-{}", variant),
+        None => "nightly".to_string(),
+    };
+    let targets = RISCV_TARGETS.join(",");
+    let args = [
+        "toolchain",
+        "install",
+        &toolchain,
+        "--profile",
+        "minimal",
+        "--component",
+        "rust-src",
+        "--target",
+        &targets,
+    ];
+    run_external_command_with_progress(
+        window.clone(),
+        cancel.clone(),
+        &rustup_path.to_string_lossy(),
+        &args,
+        &envs,
+        "PROGRESS_EVENT",
+    )
+    .await
+    .map_err(|_| "Failed to install the nightly toolchain".to_string())?;
+
+    let toolchain_dir = rust_dir("RUSTUP_HOME", ".rustup")?
+        .join("toolchains")
+        .join(ESP_TOOLCHAIN);
+    info!(
+        "Extracting the Xtensa toolchain to {}",
+        toolchain_dir.display()
+    );
+    let _ = std::fs::remove_dir_all(&toolchain_dir);
+    unzip(
+        window,
+        cancel.clone(),
+        bundle.file_path(esp_toolchain).display().to_string(),
+        toolchain_dir.display().to_string(),
+    )
+    .map_err(|e| format!("Failed to extract the Xtensa toolchain: {}", e))?;
+    if cancel.is_cancelled() {
+        return Err("Installation aborted".to_string());
+    }
+
+    let export_file = write_export_file(&toolchain_dir)?;
+    info!(
+        "Environment of the Xtensa toolchain written to {}",
+        export_file.display()
+    );
+    Ok(())
+}
+
+// Command to check an offline bundle before installing from it. Extracting
+// and hashing a bundle of several GB takes a while, it runs as a job.
+#[tauri::command]
+pub async fn validate_offline_bundle(
+    window: Window,
+    app: AppHandle,
+    bundle_path: String,
+) -> Result<BundleManifest, String> {
+    let (job_id, cancel) = start_job(&app, JobKind::Validate, &bundle_path);
+    // The bundle of an earlier check is removed, it is validated again
+    drop(app.state::<ValidatedBundles>().take(&bundle_path));
+
+    let path = bundle_path.clone();
+    let validate_handle = tokio::task::spawn_blocking(move || {
+        let bundle = OfflineBundle::open(&window, &cancel, &path)?;
+        bundle.validate(&cancel)?;
+        Ok::<_, String>(bundle)
+    });
+
+    let result = validate_handle.await;
+
+    finish_job(&app, job_id, matches!(result, Ok(Ok(_))));
+
+    match result {
+        Ok(result) => {
+            let bundle = result?;
+            let manifest = bundle.manifest.clone();
+            app.state::<ValidatedBundles>().insert(&bundle_path, bundle);
+            Ok(manifest)
+        }
+        Err(_) => Err("Validation task panicked".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    // Bundle directory with the given files, removed when dropped.
+    struct BundleDir(PathBuf);
+
+    impl BundleDir {
+        fn new(name: &str, files: &[(&str, &[u8])]) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("esp-workbench-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            for (file, content) in files {
+                let path = dir.join(file);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, content).unwrap();
+            }
+            BundleDir(dir)
+        }
+
+        fn bundle(&self, manifest: BundleManifest) -> OfflineBundle {
+            OfflineBundle {
+                root: self.0.clone(),
+                manifest,
+                extracted: false,
+            }
+        }
+    }
+
+    impl Drop for BundleDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn bundle_file(path: &str, content: &[u8]) -> BundleFile {
+        BundleFile {
+            path: path.to_string(),
+            sha256: format!("{:x}", Sha256::digest(content)),
+        }
+    }
+
+    fn mirror(files: &[&str]) -> BundleMirror {
+        BundleMirror {
+            path: "rust".to_string(),
+            files: files.iter().map(|file| bundle_file(file, b"")).collect(),
+        }
+    }
+
+    #[test]
+    fn check_paths() {
+        let cases = [
+            ("manifest.json", true),
+            ("dist/xtensa-esp-elf-13.2.0.tar.xz", true),
+            ("python/idf_component_manager-1.4.2-py3-none-any.whl", true),
+            ("dist/./esp-idf.zip", true),
+            // Dots inside a name are no parent directory
+            ("dist/..hidden", true),
+            ("dist/esp-idf..zip", true),
+            ("../esp-idf.zip", false),
+            ("dist/../../esp-idf.zip", false),
+            ("dist\\..\\..\\esp-idf.zip", false),
+            ("..", false),
+            ("/etc/passwd", false),
+        ];
+        for (path, valid) in cases {
+            assert_eq!(check_path(path).is_ok(), valid, "{}", path);
+        }
+    }
+
+    #[test]
+    fn file_urls() {
+        let cases = [
+            ("/tmp/bundle/rust", "file:///tmp/bundle/rust"),
+            ("/", "file:///"),
+            (
+                "C:\\Users\\esp\\bundle\\rust",
+                "file:///C:/Users/esp/bundle/rust",
+            ),
+            ("D:/bundle/rust", "file:///D:/bundle/rust"),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(file_url(path), expected);
+        }
+    }
+
+    #[test]
+    fn rustup_env_of_bundle() {
+        let dir = BundleDir::new("bundle-rustup-env", &[]);
+        let url = file_url(&dir.0.join("rust").display().to_string());
+        let bundle = dir.bundle(BundleManifest {
+            rust_dist: Some(mirror(&[])),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            bundle.rustup_env().unwrap(),
+            vec![
+                ("RUSTUP_DIST_SERVER".to_string(), url.clone()),
+                ("RUSTUP_UPDATE_ROOT".to_string(), format!("{}/rustup", url)),
+            ]
+        );
+        assert!(url.starts_with("file:///"));
+
+        // Without a distribution, or one outside of the bundle
+        assert!(dir.bundle(BundleManifest::default()).rustup_env().is_err());
+        let outside = BundleMirror {
+            path: "../rust".to_string(),
+            files: Vec::new(),
+        };
+        let bundle = dir.bundle(BundleManifest {
+            rust_dist: Some(outside),
+            ..Default::default()
+        });
+        assert!(bundle.rustup_env().is_err());
+    }
+
+    #[test]
+    fn deserialize_manifest() {
+        let manifest: BundleManifest = serde_json::from_str("{}").unwrap();
+        assert!(manifest.esp_idf_version.is_none());
+        assert_eq!(manifest.files().count(), 0);
+
+        let manifest: BundleManifest = serde_json::from_str(
+            r#"{
+                "esp_idf_version": "v5.1.2",
+                "esp_idf": {"path": "esp-idf-v5.1.2.zip", "sha256": "aa"},
+                "tools": [
+                    {"path": "dist/xtensa-esp-elf.tar.xz", "sha256": "bb"},
+                    {"path": "dist/riscv32-esp-elf.tar.xz", "sha256": "cc"}
+                ],
+                "rust_dist": {
+                    "path": "rust",
+                    "files": [{"path": "rust/dist/channel-rust-nightly.toml", "sha256": "dd"}]
+                },
+                "created_by": "bundle tool"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(manifest.esp_idf_version.as_deref(), Some("v5.1.2"));
+        assert!(manifest.python_wheels.is_empty());
+        assert!(manifest.esp_toolchain.is_none());
+        let paths: Vec<&str> = manifest.files().map(|file| file.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "esp-idf-v5.1.2.zip",
+                "dist/xtensa-esp-elf.tar.xz",
+                "dist/riscv32-esp-elf.tar.xz",
+                "rust/dist/channel-rust-nightly.toml",
+            ]
+        );
+
+        // Files need a checksum
+        let invalid = r#"{"tools": [{"path": "dist/xtensa-esp-elf.tar.xz"}]}"#;
+        assert!(serde_json::from_str::<BundleManifest>(invalid).is_err());
+    }
+
+    #[test]
+    fn validate_bundle() {
+        let dir = BundleDir::new(
+            "bundle-validate",
+            &[
+                ("esp-idf.zip", b"esp-idf"),
+                ("dist/xtensa-esp-elf.tar.xz", b"xtensa"),
+            ],
+        );
+        let esp_idf = bundle_file("esp-idf.zip", b"esp-idf");
+        let tool = bundle_file("dist/xtensa-esp-elf.tar.xz", b"xtensa");
+
+        // tools, expected error
+        let cases = [
+            (vec![tool.clone()], None),
+            // sha256 in upper case
+            (
+                vec![BundleFile {
+                    sha256: tool.sha256.to_uppercase(),
+                    ..tool.clone()
+                }],
+                None,
+            ),
+            (
+                vec![bundle_file("dist/xtensa-esp-elf.tar.xz", b"other")],
+                Some("Checksum mismatch"),
+            ),
+            (
+                vec![bundle_file("dist/missing.tar.xz", b"")],
+                Some("Failed to read"),
+            ),
+            (
+                vec![bundle_file("../esp-idf.zip", b"esp-idf")],
+                Some("Invalid path"),
+            ),
+        ];
+        for (tools, error) in cases {
+            let bundle = dir.bundle(BundleManifest {
+                esp_idf: Some(esp_idf.clone()),
+                tools,
+                ..Default::default()
+            });
+            match (bundle.validate(&CancellationToken::default()), error) {
+                (Ok(()), None) => {}
+                (Err(message), Some(error)) => assert!(message.contains(error), "{}", message),
+                (result, error) => panic!("{:?} instead of {:?}", result, error),
+            }
+        }
+
+        let cancel = CancellationToken::default();
+        cancel.cancel();
+        let bundle = dir.bundle(BundleManifest {
+            esp_idf: Some(esp_idf),
+            ..Default::default()
+        });
+        assert!(bundle.validate(&cancel).is_err());
+    }
+
+    #[test]
+    fn check_nightly_archives() {
+        let mut files = vec![
+            "rust/dist/channel-rust-nightly.toml",
+            "rust/dist/2024-01-25/rust-src-nightly.tar.xz",
+            "rust/dist/2024-01-25/rustc-nightly-x86_64-unknown-linux-gnu.tar.xz",
+            "rust/dist/2024-01-25/cargo-nightly-x86_64-unknown-linux-gnu.tar.xz",
+            "rust/dist/2024-01-25/rust-std-nightly-x86_64-unknown-linux-gnu.tar.gz",
+        ];
+        let targets: Vec<String> = RISCV_TARGETS
+            .iter()
+            .map(|target| format!("rust/dist/2024-01-25/rust-std-nightly-{}.tar.xz", target))
+            .collect();
+        files.extend(targets.iter().map(|target| target.as_str()));
+        let complete = mirror(&files);
+
+        let host = Some("x86_64-unknown-linux-gnu");
+        assert!(complete.check_archives(&nightly_components(host)).is_ok());
+        assert!(complete.check_archives(&nightly_components(None)).is_ok());
+        let other_host = nightly_components(Some("x86_64-pc-windows-msvc"));
+        assert!(complete.check_archives(&other_host).is_err());
+
+        // Each missing archive is reported
+        for missing in &files[1..] {
+            let files: Vec<&str> = files
+                .iter()
+                .filter(|file| file != &missing)
+                .copied()
+                .collect();
+            let result = mirror(&files).check_archives(&nightly_components(host));
+            assert!(result.is_err(), "{}", missing);
+        }
+        // The name has to match up to the extension
+        let similar = mirror(&["rust/dist/rust-src-nightly-old.tar.xz"]);
+        assert!(similar
+            .check_archives(&nightly_components(None)[..1])
+            .is_err());
+    }
+
+    #[test]
+    fn check_rust_toolchains_of_bundle() {
+        let dir = BundleDir::new("bundle-rust-toolchains", &[]);
+        let mut files = vec!["rust/dist/rust-src-nightly.tar.xz".to_string()];
+        for target in RISCV_TARGETS {
+            files.push(format!("rust/dist/rust-std-nightly-{}.tar.xz", target));
+        }
+        let files: Vec<&str> = files.iter().map(|file| file.as_str()).collect();
+        let esp_toolchain = Some(bundle_file("esp.zip", b""));
+
+        // rust_dist, esp_toolchain, valid
+        let cases = [
+            (Some(mirror(&files)), esp_toolchain.clone(), true),
+            (Some(mirror(&files[1..])), esp_toolchain.clone(), false),
+            (None, esp_toolchain, false),
+            (Some(mirror(&files)), None, false),
+        ];
+        for (rust_dist, esp_toolchain, valid) in cases {
+            let bundle = dir.bundle(BundleManifest {
+                rust_dist,
+                esp_toolchain,
+                ..Default::default()
+            });
+            assert_eq!(bundle.check_rust_toolchains(None).is_ok(), valid);
+        }
+    }
+
+    #[test]
+    fn find_versioned_dirs() {
+        let dir = BundleDir::new(
+            "bundle-versioned-dir",
+            &[
+                (
+                    "xtensa-esp-elf/esp-13.2.0_20230928/xtensa-esp-elf/bin/xtensa-esp32-elf-gcc",
+                    b"",
+                ),
+                ("riscv32-esp-elf/esp-13.2.0_20230928/README", b""),
+            ],
+        );
+
+        let found = versioned_dir(&dir.0.join("xtensa-esp-elf"), &["xtensa-esp-elf", "bin"]);
+        assert_eq!(
+            found,
+            Some(
+                dir.0
+                    .join("xtensa-esp-elf/esp-13.2.0_20230928/xtensa-esp-elf/bin")
+            )
+        );
+        // The inner directories are missing, or the directory itself
+        assert!(
+            versioned_dir(&dir.0.join("riscv32-esp-elf"), &["riscv32-esp-elf", "bin"]).is_none()
+        );
+        assert!(versioned_dir(&dir.0.join("missing"), &["bin"]).is_none());
+        // Files are no directories
+        assert!(versioned_dir(&dir.0.join("riscv32-esp-elf"), &["README"]).is_none());
+    }
+
+    #[test]
+    fn export_file_of_toolchain() {
+        let clang = format!(
+            "xtensa-esp32-elf-clang/esp-17.0.1_20240419/esp-clang/{}/libclang",
+            LIBCLANG_DIR
+        );
+        let dir = BundleDir::new(
+            "bundle-export-file",
+            &[
+                (&clang, b""),
+                (
+                    "xtensa-esp-elf/esp-13.2.0_20230928/xtensa-esp-elf/bin/gcc",
+                    b"",
+                ),
+            ],
+        );
+
+        let content = export_file_content(&dir.0).unwrap();
+        let libclang = dir
+            .0
+            .join("xtensa-esp32-elf-clang/esp-17.0.1_20240419/esp-clang")
+            .join(LIBCLANG_DIR);
+        let gcc = dir
+            .0
+            .join("xtensa-esp-elf/esp-13.2.0_20230928/xtensa-esp-elf/bin");
+        assert_eq!(content.lines().count(), 2);
+        assert!(content.contains(&libclang.display().to_string()));
+        assert!(content.contains(&gcc.display().to_string()));
+        assert!(!content.contains("riscv32-esp-elf"));
+
+        // LLVM is required
+        let dir = BundleDir::new("bundle-export-file-no-llvm", &[]);
+        assert!(export_file_content(&dir.0).is_err());
+    }
+}
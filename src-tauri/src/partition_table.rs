@@ -0,0 +1,369 @@
+use esp_idf_part::{AppType, DataType, Partition, PartitionTable, SubType, Type};
+
+use crate::flasher::parse_flash_size;
+use crate::flasher_args::parse_offset;
+
+// Default location of the partition table in ESP-IDF projects.
+pub const PARTITION_TABLE_OFFSET: u32 = 0x8000;
+// Maximal length of the binary partition table including the MD5 entry.
+pub const PARTITION_TABLE_SIZE: u32 = 0xC00;
+// The partition table occupies a whole sector, partitions may start after it.
+const FIRST_PARTITION_OFFSET: u32 = PARTITION_TABLE_OFFSET + 0x1000;
+const FLASH_SECTOR_SIZE: u32 = 0x1000;
+
+// Partition as exchanged with the frontend, types use the names from ESP-IDF CSV files.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct PartitionEntry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub subtype: String,
+    pub offset: u32,
+    pub size: u32,
+    pub encrypted: bool,
+}
+
+impl From<&Partition> for PartitionEntry {
+    fn from(partition: &Partition) -> Self {
+        Self {
+            name: partition.name(),
+            ty: partition.ty().to_string(),
+            subtype: partition.subtype().to_string(),
+            offset: partition.offset(),
+            size: partition.size(),
+            encrypted: partition.encrypted(),
+        }
+    }
+}
+
+fn parse_type(value: &str) -> Result<Type, String> {
+    match value {
+        "app" => Ok(Type::App),
+        "data" => Ok(Type::Data),
+        _ => match parse_offset(value) {
+            Ok(ty) if ty <= 0xFE => Ok(Type::from(ty as u8)),
+            _ => Err(format!("Invalid partition type: {}", value)),
+        },
+    }
+}
+
+fn parse_subtype(ty: Type, value: &str) -> Result<SubType, String> {
+    let subtype = match ty {
+        Type::App => value.parse::<AppType>().ok().map(SubType::App),
+        Type::Data => value.parse::<DataType>().ok().map(SubType::Data),
+        Type::Custom(_) => None,
+    };
+    match subtype {
+        Some(subtype) => Ok(subtype),
+        None => match parse_offset(value) {
+            Ok(subtype) if subtype <= 0xFF => Ok(SubType::Custom(subtype as u8)),
+            _ => Err(format!("Invalid partition subtype: {}", value)),
+        },
+    }
+}
+
+fn to_partition(entry: &PartitionEntry) -> Result<Partition, String> {
+    let ty = parse_type(&entry.ty)?;
+    let subtype = parse_subtype(ty, &entry.subtype)?;
+    Ok(Partition::new(
+        entry.name.clone(),
+        ty,
+        subtype,
+        entry.offset,
+        entry.size,
+        entry.encrypted,
+    ))
+}
+
+fn to_partition_table(entries: &[PartitionEntry]) -> Result<PartitionTable, String> {
+    let partitions = entries
+        .iter()
+        .map(to_partition)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(PartitionTable::new(partitions))
+}
+
+// Parse either the binary format (e.g. as read from flash) or CSV.
+pub fn parse_partition_table(data: Vec<u8>) -> Result<Vec<PartitionEntry>, String> {
+    let table = PartitionTable::try_from(data)
+        .map_err(|err| format!("Failed to parse partition table: {}", err))?;
+    Ok(table
+        .partitions()
+        .iter()
+        .map(PartitionEntry::from)
+        .collect())
+}
+
+pub fn find_partition<'a>(
+    partitions: &'a [PartitionEntry],
+    name: &str,
+) -> Result<&'a PartitionEntry, String> {
+    partitions
+        .iter()
+        .find(|partition| partition.name == name)
+        .ok_or_else(|| format!("Partition '{}' not found", name))
+}
+
+// Returns all problems found in the table, an empty list means the table is valid.
+pub fn validate(partitions: &[PartitionEntry], flash_size: Option<u32>) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    match to_partition_table(partitions) {
+        Ok(table) => {
+            if let Err(err) = table.validate() {
+                errors.push(err.to_string());
+            }
+        }
+        Err(err) => errors.push(err),
+    }
+
+    for partition in partitions {
+        if partition.offset < FIRST_PARTITION_OFFSET {
+            errors.push(format!(
+                "Partition '{}' overlaps the bootloader or the partition table",
+                partition.name
+            ));
+        }
+        if partition.size == 0 || partition.size % FLASH_SECTOR_SIZE != 0 {
+            errors.push(format!(
+                "Size of partition '{}' is not a multiple of 0x{:x}",
+                partition.name, FLASH_SECTOR_SIZE
+            ));
+        }
+        if let Some(flash_size) = flash_size {
+            if partition.offset as u64 + partition.size as u64 > flash_size as u64 {
+                errors.push(format!(
+                    "Partition '{}' ends beyond the flash size (0x{:x} bytes)",
+                    partition.name, flash_size
+                ));
+            }
+        }
+    }
+
+    errors
+}
+
+// Command to load a partition table from a CSV or binary file
+#[tauri::command]
+pub fn load_partition_table(path: String) -> Result<Vec<PartitionEntry>, String> {
+    let data = std::fs::read(&path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+    parse_partition_table(data)
+}
+
+// Command to check a partition table, flash size is given as e.g. "4MB"
+#[tauri::command]
+pub fn validate_partition_table(
+    partitions: Vec<PartitionEntry>,
+    flash_size: Option<String>,
+) -> Result<Vec<String>, String> {
+    let flash_size = match flash_size {
+        Some(flash_size) => parse_flash_size(&flash_size)?.map(|size| size.size()),
+        None => None,
+    };
+    Ok(validate(&partitions, flash_size))
+}
+
+// Command to write a partition table as "csv" or "bin" file
+#[tauri::command]
+pub fn save_partition_table(
+    partitions: Vec<PartitionEntry>,
+    path: String,
+    format: String,
+) -> Result<(), String> {
+    let table = to_partition_table(&partitions)?;
+    table.validate().map_err(|err| err.to_string())?;
+
+    let data = match format.as_str() {
+        "csv" => table.to_csv().map(String::into_bytes),
+        "bin" => table.to_bin(),
+        _ => return Err(format!("Unsupported partition table format: {}", format)),
+    }
+    .map_err(|err| format!("Failed to convert partition table: {}", err))?;
+
+    std::fs::write(&path, data).map_err(|err| format!("Failed to write {}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u32 = 1024 * 1024;
+
+    fn entry(name: &str, ty: &str, subtype: &str, offset: u32, size: u32) -> PartitionEntry {
+        PartitionEntry {
+            name: name.to_string(),
+            ty: ty.to_string(),
+            subtype: subtype.to_string(),
+            offset,
+            size,
+            encrypted: false,
+        }
+    }
+
+    // Valid factory app followed by the given partition
+    fn with_app(partition: PartitionEntry) -> Vec<PartitionEntry> {
+        vec![
+            entry("factory", "app", "factory", 0x10000, 0x100000),
+            partition,
+        ]
+    }
+
+    // Default single app layout of ESP-IDF
+    fn default_table() -> Vec<PartitionEntry> {
+        vec![
+            entry("nvs", "data", "nvs", 0x9000, 0x6000),
+            entry("phy_init", "data", "phy", 0xf000, 0x1000),
+            entry("factory", "app", "factory", 0x10000, 0x100000),
+        ]
+    }
+
+    #[test]
+    fn parse_csv_table() {
+        let csv = "# Name,   Type, SubType, Offset,  Size, Flags\n\
+                   nvs,      data, nvs,     0x9000,  0x6000,\n\
+                   phy_init, data, phy,     0xf000,  0x1000,\n\
+                   factory,  app,  factory, 0x10000, 1M,\n";
+        let partitions = parse_partition_table(csv.as_bytes().to_vec()).unwrap();
+        let names: Vec<&str> = partitions.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["nvs", "phy_init", "factory"]);
+        assert_eq!(partitions[2].ty, "app");
+        assert_eq!(partitions[2].subtype, "factory");
+        assert_eq!(partitions[2].offset, 0x10000);
+        assert_eq!(partitions[2].size, MB);
+    }
+
+    #[test]
+    fn binary_table_round_trip() {
+        let table = to_partition_table(&default_table()).unwrap();
+        let partitions = parse_partition_table(table.to_bin().unwrap()).unwrap();
+        assert_eq!(partitions.len(), 3);
+        assert_eq!(partitions[0].name, "nvs");
+        assert_eq!(partitions[1].subtype, "phy");
+        assert_eq!(partitions[2].offset, 0x10000);
+    }
+
+    #[test]
+    fn parse_invalid_table() {
+        assert!(parse_partition_table(b"not a partition table".to_vec()).is_err());
+    }
+
+    #[test]
+    fn parse_types_and_subtypes() {
+        let cases = [
+            ("app", "factory", true),
+            ("app", "ota_0", true),
+            ("app", "0x20", true),
+            ("data", "nvs", true),
+            ("data", "coredump", true),
+            ("data", "0x99", true),
+            ("0x40", "0x01", true),
+            ("64", "1", true),
+            ("data", "factory", false),
+            ("app", "0x100", false),
+            ("0xff", "0x00", false),
+            ("bootloader", "0x00", false),
+            ("0x40", "nvs", false),
+        ];
+        for (ty, subtype, valid) in cases {
+            let result = to_partition(&entry("part", ty, subtype, 0x10000, 0x1000));
+            assert_eq!(result.is_ok(), valid, "{} {}", ty, subtype);
+        }
+    }
+
+    #[test]
+    fn find_partition_by_name() {
+        let partitions = default_table();
+        assert_eq!(
+            find_partition(&partitions, "factory").unwrap().offset,
+            0x10000
+        );
+        assert!(find_partition(&partitions, "ota_0").is_err());
+    }
+
+    #[test]
+    fn validate_tables() {
+        // (description, partitions, flash size, expected error or None for a valid table)
+        let cases = [
+            ("default layout", default_table(), Some(4 * MB), None),
+            ("unknown flash size", default_table(), None, None),
+            (
+                "exactly filling the flash",
+                vec![entry("factory", "app", "factory", 0x10000, MB - 0x10000)],
+                Some(MB),
+                None,
+            ),
+            (
+                "overlapping the bootloader",
+                with_app(entry("nvs", "data", "nvs", 0x1000, 0x6000)),
+                None,
+                Some("overlaps the bootloader"),
+            ),
+            (
+                "overlapping the partition table",
+                with_app(entry("nvs", "data", "nvs", 0x8000, 0x6000)),
+                None,
+                Some("overlaps the bootloader"),
+            ),
+            (
+                "size not sector aligned",
+                with_app(entry("nvs", "data", "nvs", 0x9000, 0x5800)),
+                None,
+                Some("is not a multiple of 0x1000"),
+            ),
+            (
+                "empty partition",
+                with_app(entry("nvs", "data", "nvs", 0x9000, 0)),
+                None,
+                Some("is not a multiple of 0x1000"),
+            ),
+            (
+                "beyond the flash size",
+                vec![entry("factory", "app", "factory", 0x10000, 2 * MB)],
+                Some(2 * MB),
+                Some("ends beyond the flash size"),
+            ),
+            (
+                "ending beyond 4 GB",
+                vec![entry("factory", "app", "factory", 0xffff_0000, 0x20000)],
+                Some(16 * MB),
+                Some("ends beyond the flash size"),
+            ),
+            (
+                "overlapping partitions",
+                vec![
+                    entry("nvs", "data", "nvs", 0x9000, 0x6000),
+                    entry("phy_init", "data", "phy", 0xe000, 0x1000),
+                    entry("factory", "app", "factory", 0x10000, 0x100000),
+                ],
+                None,
+                Some("overlap"),
+            ),
+            (
+                "misaligned app",
+                vec![entry("factory", "app", "factory", 0x11000, 0x100000)],
+                None,
+                Some("aligned"),
+            ),
+            (
+                "invalid subtype",
+                with_app(entry("nvs", "data", "ota_0", 0x9000, 0x6000)),
+                None,
+                Some("invalid partition subtype"),
+            ),
+        ];
+
+        for (description, partitions, flash_size, expected) in cases {
+            let errors = validate(&partitions, flash_size);
+            match expected {
+                None => assert!(errors.is_empty(), "{}: {:?}", description, errors),
+                // Only the problem under test is reported
+                Some(expected) => assert!(
+                    errors.len() == 1 && errors[0].to_lowercase().contains(expected),
+                    "{}: {:?}",
+                    description,
+                    errors
+                ),
+            }
+        }
+    }
+}
@@ -1,14 +1,17 @@
 use std::process::Command;
 
-use tauri::{AppHandle, Window};
+use tauri::{AppHandle, Manager, Window};
 
 use external_command::run_external_command_with_progress;
 
 use log::info;
 
+use crate::app_state::{finish_job, start_job, CancellationToken, JobKind};
 use crate::download::download_file;
 use crate::external_command;
 use crate::external_command::set_exec_permission;
+use crate::network::load_network_settings;
+use crate::offline_bundle::{install_rust_toolchains_from_bundle, open_validated, OfflineBundle};
 
 #[cfg(windows)]
 use std::os::windows::process::CommandExt;
@@ -112,6 +115,10 @@ pub struct RustInstallOptions {
     selected_variant: Option<String>,
     install_msvc: bool,
     install_mingw: bool,
+    // Install rustup, espup and the toolchains from this offline bundle instead
+    // of downloading them
+    #[serde(default)]
+    offline_bundle: Option<String>,
 }
 
 #[tauri::command]
@@ -119,26 +126,72 @@ pub async fn install_rust_support(
     window: Window,
     app: AppHandle,
     install_options: RustInstallOptions,
+) -> Result<String, String> {
+    let (job_id, cancel) = start_job(&app, JobKind::Install, "rust");
+    let result = run_rust_install(window, cancel, install_options).await;
+    finish_job(&app, job_id, result.is_ok());
+    result
+}
+
+async fn run_rust_install(
+    window: Window,
+    cancel: CancellationToken,
+    install_options: RustInstallOptions,
 ) -> Result<String, String> {
     let selected_variant = install_options.selected_variant;
     #[cfg(target_os = "windows")]
     {
         if install_options.install_msvc {
-            install_vc_tools_and_sdk(window.clone(), app.clone()).await?;
+            install_vc_tools_and_sdk(window.clone(), cancel.clone()).await?;
         }
     }
 
-    download_rustup(window.clone(), app.clone()).await?;
-    install_rustup(window.clone(), app.clone(), selected_variant.as_ref()).await?;
-    install_espup(window.clone(), app.clone(), selected_variant.as_ref()).await?;
-    install_rust_toolchain(window, app, selected_variant.as_ref()).await?;
+    let bundle = match install_options.offline_bundle.as_deref() {
+        Some(path) => {
+            let bundle = open_validated(&window, &cancel, path)?;
+            bundle.check_rust_toolchains(selected_variant.as_deref())?;
+            Some(bundle)
+        }
+        None => None,
+    };
+
+    // rustup-init installs the stable toolchain from the bundle as well
+    let rustup_envs = match &bundle {
+        Some(bundle) => bundle.rustup_env()?,
+        None => Vec::new(),
+    };
+    download_rustup(window.clone(), cancel.clone(), bundle.as_ref()).await?;
+    install_rustup(
+        window.clone(),
+        cancel.clone(),
+        selected_variant.as_ref(),
+        rustup_envs,
+    )
+    .await?;
+    install_espup(
+        window.clone(),
+        cancel.clone(),
+        selected_variant.as_ref(),
+        bundle.as_ref(),
+    )
+    .await?;
+    match &bundle {
+        Some(bundle) => {
+            install_rust_toolchains_from_bundle(window, cancel, bundle, selected_variant.as_ref())
+                .await?
+        }
+        None => {
+            install_rust_toolchain(window, cancel, selected_variant.as_ref()).await?;
+        }
+    }
     Ok("Success".into())
 }
 
 pub async fn install_rustup(
     window: Window,
-    app: tauri::AppHandle,
+    cancel: CancellationToken,
     selected_variant: Option<&String>,
+    extra_envs: Vec<(String, String)>,
 ) -> Result<String, String> {
     #[cfg(windows)]
     let fname = "rustup-init.exe";
@@ -152,6 +205,8 @@ pub async fn install_rustup(
     let rustup_path = output_dir.join(fname).to_str().unwrap().to_string();
 
     info!("Installing rustup...");
+    let mut envs = load_network_settings(&window.app_handle()).command_env();
+    envs.extend(extra_envs);
 
     #[cfg(target_os = "windows")]
     {
@@ -164,12 +219,14 @@ pub async fn install_rustup(
 
         run_external_command_with_progress(
             window.clone(),
-            app,
+            cancel,
             &rustup_path,
             &args,
+            &envs,
             "PROGRESS_EVENT",
         )
-        .await;
+        .await
+        .map_err(|_| "Failed to install rustup".to_string())?;
     }
 
     #[cfg(unix)]
@@ -177,30 +234,38 @@ pub async fn install_rustup(
         let args = vec!["-y"];
         run_external_command_with_progress(
             window.clone(),
-            app,
+            cancel,
             &rustup_path,
             &args,
+            &envs,
             "PROGRESS_EVENT",
         )
-        .await;
+        .await
+        .map_err(|_| "Failed to install rustup".to_string())?;
     }
 
     info!("Rustup installed or already present");
     Ok("Rustup installed or already present".into())
 }
 
-async fn download_rustup(window: Window, app: AppHandle) -> Result<String, String> {
+async fn download_rustup(
+    window: Window,
+    cancel: CancellationToken,
+    bundle: Option<&OfflineBundle>,
+) -> Result<String, String> {
     info!("Downloading rustup...");
 
-    let url: &'static str;
+    let default_url: &'static str;
     #[cfg(target_os = "windows")]
     {
-        url = "https://win.rustup.rs/x86_64";
+        default_url = "https://win.rustup.rs/x86_64";
     }
     #[cfg(any(target_os = "linux", target_os = "macos"))]
     {
-        url = "https://sh.rustup.rs";
+        default_url = "https://sh.rustup.rs";
     }
+    let settings = load_network_settings(&window.app_handle());
+    let url = settings.rustup_init_url(default_url);
 
     #[cfg(windows)]
     let fname = "rustup-init.exe";
@@ -221,10 +286,20 @@ async fn download_rustup(window: Window, app: AppHandle) -> Result<String, Strin
         }
     }
 
-    // Use the download_file function to download the file
-    download_file(window.clone(), app.clone(), url, &output_path)
-        .await
-        .map_err(|e| format!("Failed to download rustup: {:?}", e))?;
+    match bundle {
+        Some(bundle) => {
+            let rustup = bundle
+                .manifest
+                .rustup
+                .as_ref()
+                .ok_or("The offline bundle contains no rustup")?;
+            bundle.install_file(rustup, &output_path)?;
+        }
+        // Use the download_file function to download the file
+        None => download_file(window.clone(), cancel.clone(), url, &output_path)
+            .await
+            .map_err(|e| format!("Failed to download rustup: {:?}", e))?,
+    }
 
     // Set execute permission for the binary on Unix-based systems
     #[cfg(unix)]
@@ -238,37 +313,41 @@ async fn download_rustup(window: Window, app: AppHandle) -> Result<String, Strin
 
 async fn install_espup(
     window: Window,
-    app: AppHandle,
+    cancel: CancellationToken,
     _selected_variant: Option<&String>,
+    bundle: Option<&OfflineBundle>,
 ) -> Result<String, String> {
     info!("Installing espup...");
 
-    let url: &'static str;
+    let asset: &'static str;
     #[cfg(target_os = "linux")]
     #[cfg(target_arch = "aarch64")]
     {
-        url = "https://github.com/esp-rs/espup/releases/latest/download/espup-aarch64-unknown-linux-gnu";
+        asset = "espup-aarch64-unknown-linux-gnu";
     }
     #[cfg(target_os = "linux")]
     #[cfg(target_arch = "x86_64")]
     {
-        url = "https://github.com/esp-rs/espup/releases/latest/download/espup-x86_64-unknown-linux-gnu";
+        asset = "espup-x86_64-unknown-linux-gnu";
     }
     #[cfg(target_os = "macos")]
     #[cfg(target_arch = "aarch64")]
     {
-        url = "https://github.com/esp-rs/espup/releases/latest/download/espup-aarch64-apple-darwin";
+        asset = "espup-aarch64-apple-darwin";
     }
     #[cfg(target_os = "macos")]
     #[cfg(target_arch = "x86_64")]
     {
-        url = "https://github.com/esp-rs/espup/releases/latest/download/espup-x86_64-apple-darwin";
+        asset = "espup-x86_64-apple-darwin";
     }
     #[cfg(target_os = "windows")]
     {
-        url = "https://github.com/esp-rs/espup/releases/latest/download/espup-x86_64-pc-windows-msvc.exe";
+        asset = "espup-x86_64-pc-windows-msvc.exe";
     }
 
+    let url = load_network_settings(&window.app_handle())
+        .github_asset_url(&format!("esp-rs/espup/releases/latest/download/{}", asset));
+
     #[cfg(unix)]
     let fname = "espup";
     #[cfg(windows)]
@@ -280,10 +359,20 @@ async fn install_espup(
         .join("bin");
     let output_path = output_dir.join(fname);
 
-    // Use the download_file function to download the file
-    download_file(window.clone(), app.clone(), url, &output_path)
-        .await
-        .map_err(|e| format!("Failed to download espup: {:?}", e))?;
+    match bundle {
+        Some(bundle) => {
+            let espup = bundle
+                .manifest
+                .espup
+                .as_ref()
+                .ok_or("The offline bundle contains no espup")?;
+            bundle.install_file(espup, &output_path)?;
+        }
+        // Use the download_file function to download the file
+        None => download_file(window.clone(), cancel.clone(), &url, &output_path)
+            .await
+            .map_err(|e| format!("Failed to download espup: {:?}", e))?,
+    }
 
     // Set execute permission for the binary on Unix-based systems
     #[cfg(unix)]
@@ -297,7 +386,7 @@ async fn install_espup(
 
 async fn install_rust_toolchain(
     window: Window,
-    app: AppHandle,
+    cancel: CancellationToken,
     selected_variant: Option<&String>,
 ) -> Result<String, String> {
     info!("Installing Rust toolchain via espup... (this might take a while)");
@@ -325,11 +414,13 @@ async fn install_rust_toolchain(
         args.push(variant);
     }
 
+    let envs = load_network_settings(&window.app_handle()).command_env();
     let result = run_external_command_with_progress(
         window.clone(),
-        app.clone(),
+        cancel.clone(),
         &espup_path,
         &args,
+        &envs,
         "PROGRESS_EVENT",
     )
     .await;
@@ -348,7 +439,10 @@ async fn install_rust_toolchain(
 
 #[cfg(target_os = "windows")]
 #[cfg(target_os = "windows")]
-async fn install_vc_tools_and_sdk(window: Window, app: tauri::AppHandle) -> Result<String, String> {
+async fn install_vc_tools_and_sdk(
+    window: Window,
+    cancel: CancellationToken,
+) -> Result<String, String> {
     info!("Downloading Visual Studio Build Tools and Windows SDK...");
 
     // Define the URL and destination path
@@ -357,7 +451,7 @@ async fn install_vc_tools_and_sdk(window: Window, app: tauri::AppHandle) -> Resu
     let dest_path = tmp_dir.join("vs_buildtools.exe");
 
     // Call the download_file function
-    download_file(window.clone(), app.clone(), url, &dest_path)
+    download_file(window.clone(), cancel.clone(), url, &dest_path)
         .await
         .map_err(|e| format!("Failed to download VS Build Tools: {}", e))?;
 
@@ -374,9 +468,10 @@ async fn install_vc_tools_and_sdk(window: Window, app: tauri::AppHandle) -> Resu
     ];
     run_external_command_with_progress(
         window,
-        app,
+        cancel,
         &dest_path.to_string_lossy(),
         &args,
+        &[],
         "Installing Visual Studio Build Tools and Windows SDK...",
     )
     .await;
@@ -0,0 +1,32 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::PathBuf;
+use tauri::AppHandle;
+
+// Settings are kept as JSON files in the application config directory.
+pub fn settings_path(app: &AppHandle, name: &str) -> Result<PathBuf, String> {
+    app.path_resolver()
+        .app_config_dir()
+        .map(|dir| dir.join(name))
+        .ok_or_else(|| "Unable to determine the application config directory".to_string())
+}
+
+// Missing or unreadable settings fall back to the defaults.
+pub fn load_settings<T: DeserializeOwned + Default>(app: &AppHandle, name: &str) -> T {
+    settings_path(app, name)
+        .ok()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_settings<T: Serialize>(app: &AppHandle, name: &str, value: &T) -> Result<(), String> {
+    let path = settings_path(app, name)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| format!("Failed to create {}: {}", parent.display(), err))?;
+    }
+    let content = serde_json::to_string_pretty(value).map_err(|err| err.to_string())?;
+    std::fs::write(&path, content)
+        .map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+}
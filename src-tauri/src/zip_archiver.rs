@@ -9,12 +9,11 @@ use walkdir::{DirEntry, WalkDir};
 use zip::result::ZipError;
 use zip::write::FileOptions;
 
-use std::sync::Mutex;
-use tauri::{Manager, Window};
+use tauri::Window;
 
 use log::info;
 
-use crate::app_state::{AppState, BuilderState};
+use crate::app_state::CancellationToken;
 
 #[derive(Clone, serde::Serialize)]
 struct Payload {
@@ -25,7 +24,7 @@ const PROGRESS_EVENT: &str = "progress";
 
 pub fn zip_dir(
     window: Window,
-    app: tauri::AppHandle,
+    cancel: CancellationToken,
     src_dir: &str,
     dst_file: &str,
     _method: zip::CompressionMethod,
@@ -43,7 +42,7 @@ pub fn zip_dir(
 
     zip_iter(
         window,
-        app,
+        cancel,
         &mut src_it.filter_map(|e| e.ok()),
         src_dir,
         archive_file,
@@ -52,15 +51,9 @@ pub fn zip_dir(
     Ok(())
 }
 
-fn is_abort_state(app: tauri::AppHandle) -> bool {
-    let state_mutex = app.state::<Mutex<AppState>>();
-    let state = state_mutex.lock().unwrap();
-    matches!(state.builder, BuilderState::Abort)
-}
-
 fn zip_iter<T>(
     _window: Window,
-    app: tauri::AppHandle,
+    cancel: CancellationToken,
     it: &mut dyn Iterator<Item = DirEntry>,
     prefix: &str,
     writer: T,
@@ -76,7 +69,7 @@ where
 
     let mut buffer = Vec::new();
     for entry in it {
-        if is_abort_state(app.clone()) {
+        if cancel.is_cancelled() {
             info!("Aborted");
             return Ok(());
         }
@@ -109,7 +102,7 @@ where
 
 pub fn unzip(
     _window: Window,
-    app: tauri::AppHandle,
+    cancel: CancellationToken,
     file_path: String,
     output_directory: String,
 ) -> Result<(), ZipError> {
@@ -119,7 +112,7 @@ pub fn unzip(
     let mut archive = zip::ZipArchive::new(file).unwrap();
 
     for i in 0..archive.len() {
-        if is_abort_state(app.clone()) {
+        if cancel.is_cancelled() {
             info!("Aborted");
             return Ok(());
         }
@@ -163,6 +156,13 @@ pub fn unzip(
             }
             let mut outfile = fs::File::create(&outpath).unwrap();
             io::copy(&mut file, &mut outfile).unwrap();
+
+            // Keep executables of toolchain archives executable
+            #[cfg(unix)]
+            if let Some(mode) = file.unix_mode() {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&outpath, fs::Permissions::from_mode(mode)).unwrap();
+            }
         }
     }
     info!("Complete");
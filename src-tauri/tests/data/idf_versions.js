@@ -0,0 +1,48 @@
+var DOCUMENTATION_VERSIONS = {
+    DEFAULTS: { has_targets: false,
+                supported_targets: [ "esp32" ]
+              },
+    VERSIONS: [
+        // latest
+        { name: "latest", has_targets: true, supported_targets: [ "esp32", "esp32s2", "esp32s3", "esp32c3", "esp32c2", "esp32c6", "esp32h2", "esp32p4" ] },
+
+        // v5.x
+        { name: "v5.2.1", has_targets: true, supported_targets: [ "esp32", "esp32s2", "esp32s3", "esp32c3", "esp32c2", "esp32c6", "esp32h2", "esp32p4" ] },
+        { name: "v5.1.3", has_targets: true, supported_targets: [ "esp32", "esp32s2", "esp32s3", "esp32c3", "esp32c2", "esp32c6", "esp32h2" ] },
+        { name: "v5.1.2", old: true, has_targets: true, supported_targets: [ "esp32", "esp32s2", "esp32s3", "esp32c3", "esp32c2", "esp32c6", "esp32h2" ] },
+        { name: "v5.0.6", has_targets: true, supported_targets: [ "esp32", "esp32s2", "esp32s3", "esp32c3", "esp32c2" ] },
+        { name: "v5.3-beta1", pre_release: true, has_targets: true, supported_targets: [ "esp32", "esp32s2", "esp32s3", "esp32c3", "esp32c2", "esp32c6", "esp32h2", "esp32p4" ] },
+        { name: "release-v5.2", has_targets: true, supported_targets: [ "esp32", "esp32s2", "esp32s3", "esp32c3", "esp32c2", "esp32c6", "esp32h2", "esp32p4" ] },
+
+        // v4.x
+        { name: "v4.4.7", has_targets: true, supported_targets: [ "esp32", "esp32s2", "esp32s3", "esp32c3" ] },
+        { name: "v4.3.7", has_targets: true, supported_targets: [ "esp32", "esp32s2", "esp32c3" ] },
+        { name: "v4.2.5", end_of_life: true, has_targets: true, supported_targets: [ "esp32", "esp32s2" ] },
+        { name: "v4.1.4", end_of_life: true },
+
+        // v3.x
+        { name: "v3.3.6", end_of_life: true },
+        { name: "v3.2.5", old: true, end_of_life: true },
+    ],
+    IDF_TARGETS: [
+       { text: 'ESP32', value: 'esp32'},
+       { text: 'ESP32-S2', value: 'esp32s2'},
+       { text: 'ESP32-S3', value: 'esp32s3'},
+       { text: 'ESP32-C3', value: 'esp32c3'},
+       { text: 'ESP32-C2', value: 'esp32c2'},
+       { text: 'ESP32-C6', value: 'esp32c6'},
+       { text: 'ESP32-H2', value: 'esp32h2'},
+       { text: 'ESP32-P4', value: 'esp32p4'},
+    ],
+    RELEASES: {
+        /* Start of the release branch and end of its support */
+        'v5.2': {start_date: '2024-02-08', end_date: '2026-08-08'},
+        'v5.1': {start_date: '2023-07-05', end_date: '2025-12-05'},
+        'v5.0': {start_date: '2022-12-02', end_date: '2025-05-29'},
+        'v4.4': {start_date: '2022-01-26', end_date: '2024-07-31'},
+        'v4.3': {start_date: '2021-06-30', end_date: '2023-12-31'},
+        'v4.2': {start_date: '2020-12-07', end_date: '2023-02-28'},
+        'v4.1': {start_date: '2020-07-21', end_date: '2022-02-21'},
+        'v3.3': {start_date: '2019-09-05', end_date: '2023-02-28'},
+    },
+};